@@ -0,0 +1,126 @@
+use crate::geometry::Ray;
+use crate::maths::*;
+
+// axis-aligned bounding box, stored as one interval per axis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
+        Self { x, y, z }
+    }
+
+    // the box that does not contain anything
+    pub fn empty() -> Self {
+        Self::new(Interval::empty(), Interval::empty(), Interval::empty())
+    }
+
+    // the box that contains everything, e.g. for an infinite plane
+    pub fn universe() -> Self {
+        Self::new(
+            Interval::universe(),
+            Interval::universe(),
+            Interval::universe(),
+        )
+    }
+
+    // false for boxes of infinite objects, which do not fit in a bounding volume hierarchy
+    pub fn is_bounded(&self) -> bool {
+        [self.x, self.y, self.z]
+            .iter()
+            .all(|interval| interval.min.is_finite() && interval.max.is_finite())
+    }
+
+    // the box spanned by two corner points, in any order
+    pub fn from_points(a: Point, b: Point) -> Self {
+        Self::new(
+            Interval::new(a.x.min(b.x), a.x.max(b.x)),
+            Interval::new(a.y.min(b.y), a.y.max(b.y)),
+            Interval::new(a.z.min(b.z), a.z.max(b.z)),
+        )
+    }
+
+    // the smallest box that contains both boxes
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Self {
+        Self::new(a.x.union(&b.x), a.y.union(&b.y), a.z.union(&b.z))
+    }
+
+    // grows the box along all axes that are thinner than a small minimum
+    pub fn padded(&self) -> Self {
+        let delta = 0.0001;
+        let pad = |interval: &Interval| {
+            if interval.size() >= delta {
+                *interval
+            } else {
+                Interval::new(interval.min - delta / 2.0, interval.max + delta / 2.0)
+            }
+        };
+        Self::new(pad(&self.x), pad(&self.y), pad(&self.z))
+    }
+
+    // axis 0, 1, 2 are x, y, z
+    pub fn axis(&self, axis: usize) -> &Interval {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        if x > y && x > z {
+            0
+        } else if y > z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point::new(self.x.center(), self.y.center(), self.z.center())
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        if x < 0.0 || y < 0.0 || z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    // slab test: intersects the ray with the three pairs of planes and checks
+    // if the resulting parameter ranges overlap within the given interval
+    pub fn hit(&self, ray: &Ray, interval: &Interval) -> bool {
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let direction = [ray.direction.x, ray.direction.y, ray.direction.z];
+        let mut t_min = interval.min;
+        let mut t_max = interval.max;
+
+        for axis in 0..3 {
+            let slab = self.axis(axis);
+            let inv_direction = 1.0 / direction[axis];
+            let t0 = (slab.min - origin[axis]) * inv_direction;
+            let t1 = (slab.max - origin[axis]) * inv_direction;
+            let (t0, t1) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
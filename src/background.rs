@@ -0,0 +1,118 @@
+use crate::color::{Color, WorkingSpace};
+use crate::environment::EnvironmentMap;
+use crate::maths::Vec3;
+use crate::scene_format::SceneError;
+use crate::sky::Sky;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// The color of rays that leave the scene without hitting anything, as described in a scene.
+// With a black background, the scene is only lit by its emissive materials.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    // a constant color in every direction
+    Solid {
+        color: Color,
+    },
+    // blends from `bottom` when looking straight down to `top` when looking straight up
+    Gradient {
+        bottom: Color,
+        top: Color,
+    },
+    // an equirectangular HDR image around the scene, turned around the y axis by `rotation`
+    // degrees and scaled by `intensity`, see EnvironmentMap
+    Environment {
+        path: PathBuf,
+        rotation: f64,
+        intensity: f64,
+    },
+    // a clear daylight sky with the sun `elevation` degrees above the horizon and turned
+    // `azimuth` degrees from -z towards +x, see Sky
+    Sky {
+        elevation: f64,
+        azimuth: f64,
+        turbidity: f64,
+        intensity: f64,
+    },
+}
+
+impl Default for Background {
+    // the white to blue daylight sky
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Color::new(1.0, 1.0, 1.0),
+            top: Color::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Background {
+    // loads the environment image, if there is one, with its colors in the working space.
+    // A relative image path is relative to `directory`.
+    pub fn build(
+        &self,
+        space: WorkingSpace,
+        directory: &Path,
+    ) -> Result<Arc<dyn Environment>, SceneError> {
+        Ok(match self {
+            Background::Solid { color } => Arc::new(Gradient {
+                bottom: space.convert_srgb(*color),
+                top: space.convert_srgb(*color),
+            }),
+            Background::Gradient { bottom, top } => Arc::new(Gradient {
+                bottom: space.convert_srgb(*bottom),
+                top: space.convert_srgb(*top),
+            }),
+            Background::Environment {
+                path,
+                rotation,
+                intensity,
+            } => Arc::new(EnvironmentMap::load(
+                &directory.join(path),
+                *rotation,
+                *intensity,
+                space,
+            )?),
+            Background::Sky {
+                elevation,
+                azimuth,
+                turbidity,
+                intensity,
+            } => Arc::new(Sky::new(
+                *elevation, *azimuth, *turbidity, *intensity, space,
+            )),
+        })
+    }
+}
+
+// The light that arrives from infinitely far away in every direction. Environments with
+// bright spots, like the sun in a photo of the sky, can be sampled like lights.
+pub trait Environment: Send + Sync {
+    fn color(&self, direction: Vec3) -> Color;
+
+    // whether `random` and `pdf_value` sample the bright parts of the environment
+    fn is_sampled(&self) -> bool {
+        false
+    }
+
+    // density per unit solid angle of the directions that `random` returns
+    fn pdf_value(&self, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    fn random(&self) -> Vec3 {
+        Vec3::new(0.0, 1.0, 0.0)
+    }
+}
+
+struct Gradient {
+    bottom: Color,
+    top: Color,
+}
+
+impl Environment for Gradient {
+    fn color(&self, direction: Vec3) -> Color {
+        let a = 0.5 * (direction.normalized().y + 1.0);
+        Vec3::lerp(self.bottom, self.top, a)
+    }
+}
@@ -0,0 +1,209 @@
+use crate::aabb::Aabb;
+use crate::geometry::*;
+use crate::maths::*;
+use crate::scene::HittableList;
+
+// A node of a bounding volume hierarchy. Every node holds up to two children, which
+// are either further nodes or the objects of the scene. A ray is only tested against
+// the children if it hits the bounding box of the node.
+pub struct BvhNode {
+    left: Box<dyn Hittable>,
+    right: Option<Box<dyn Hittable>>,
+    bbox: Aabb,
+    depth: usize, // depth of the subtree below this node, a single leaf has depth 1
+}
+
+// statistics about the built hierarchy, for display purposes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BvhStats {
+    pub build_duration: std::time::Duration,
+    pub depth: usize,
+}
+
+// a primitive that has not been placed in the hierarchy yet
+struct BuildItem {
+    object: Box<dyn Hittable>,
+    bbox: Aabb,
+    centroid: Point,
+}
+
+impl BvhNode {
+    // builds the hierarchy from all objects in the list, using the surface area heuristic
+    // returns None if the list is empty
+    pub fn new(list: HittableList) -> Option<Self> {
+        let items: Vec<BuildItem> = list
+            .into_objects()
+            .into_iter()
+            .map(|object| {
+                let bbox = object.bounding_box();
+                BuildItem {
+                    object,
+                    bbox,
+                    centroid: bbox.centroid(),
+                }
+            })
+            .collect();
+        if items.is_empty() {
+            return None;
+        }
+        Some(Self::build(items))
+    }
+
+    // builds the hierarchy and measures how long it took
+    pub fn with_stats(list: HittableList) -> Option<(Self, BvhStats)> {
+        let start = std::time::Instant::now();
+        let node = Self::new(list)?;
+        let stats = BvhStats {
+            build_duration: start.elapsed(),
+            depth: node.depth,
+        };
+        Some((node, stats))
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    fn build(mut items: Vec<BuildItem>) -> Self {
+        if items.len() <= 2 {
+            let right = items.pop().unwrap();
+            let left = items.pop();
+            return match left {
+                Some(left) => Self::from_children(left.object, Some(right.object), 1),
+                None => Self::from_children(right.object, None, 1),
+            };
+        }
+
+        // sort along the axis in which the centroids are spread the most
+        let centroid_bounds = items.iter().fold(Aabb::empty(), |bbox, item| {
+            Aabb::surrounding(&bbox, &Aabb::from_points(item.centroid, item.centroid))
+        });
+        let axis = centroid_bounds.longest_axis();
+        items.sort_by(|a, b| {
+            let a = centroid_axis(&a.centroid, axis);
+            let b = centroid_axis(&b.centroid, axis);
+            a.total_cmp(&b)
+        });
+
+        let split = Self::find_sah_split(&items);
+        let right_items = items.split_off(split);
+        let left = Self::build(items);
+        let right = Self::build(right_items);
+        let depth = left.depth.max(right.depth) + 1;
+        Self::from_children(Box::new(left), Some(Box::new(right)), depth)
+    }
+
+    // finds the split index into the sorted items that minimizes the surface area heuristic,
+    // i.e. the sum of the child box areas weighted by the number of objects inside them
+    fn find_sah_split(items: &[BuildItem]) -> usize {
+        let count = items.len();
+
+        // area of the boxes enclosing all items to the right of each split position
+        let mut right_areas = vec![0.0; count];
+        let mut right_bbox = Aabb::empty();
+        for i in (1..count).rev() {
+            right_bbox = Aabb::surrounding(&right_bbox, &items[i].bbox);
+            right_areas[i] = right_bbox.surface_area();
+        }
+
+        let mut best_split = count / 2;
+        let mut best_cost = f64::INFINITY;
+        let mut left_bbox = Aabb::empty();
+        for split in 1..count {
+            left_bbox = Aabb::surrounding(&left_bbox, &items[split - 1].bbox);
+            let cost = left_bbox.surface_area() * split as f64
+                + right_areas[split] * (count - split) as f64;
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+        best_split
+    }
+
+    fn from_children(
+        left: Box<dyn Hittable>,
+        right: Option<Box<dyn Hittable>>,
+        depth: usize,
+    ) -> Self {
+        let bbox = match &right {
+            Some(right) => Aabb::surrounding(&left.bounding_box(), &right.bounding_box()),
+            None => left.bounding_box(),
+        };
+        Self {
+            left,
+            right,
+            bbox,
+            depth,
+        }
+    }
+}
+
+fn centroid_axis(centroid: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => centroid.x,
+        1 => centroid.y,
+        _ => centroid.z,
+    }
+}
+
+impl Hittable for BvhNode {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        if !self.bbox.hit(ray, interval) {
+            return None;
+        }
+
+        let hit_left = self.left.hit(ray, interval);
+        // the right child only needs to report hits closer than the left one
+        let right_max = hit_left.as_ref().map_or(interval.max, |hit| hit.t);
+        let hit_right = self
+            .right
+            .as_ref()
+            .and_then(|right| right.hit(ray, &Interval::new(interval.min, right_max)));
+
+        hit_right.or(hit_left)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::random::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn bvh_hits_match_linear_list() {
+        let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let mut list = HittableList::new();
+        let mut bvh_list = HittableList::new();
+        for _ in 0..200 {
+            let sphere = Sphere::new(
+                random_vec3_min_max(-10.0, 10.0),
+                random_double_range(0.1, 1.0),
+                material.clone(),
+            );
+            list.add(Box::new(sphere.clone()));
+            bvh_list.add(Box::new(sphere));
+        }
+        let bvh = BvhNode::new(bvh_list).unwrap();
+        assert!(bvh.depth() > 1);
+
+        for _ in 0..1000 {
+            let ray = Ray::new(random_vec3_min_max(-12.0, 12.0), random_vec3_unit());
+            let interval = Interval::new(0.0001, f64::INFINITY);
+            let expected = list.hit(&ray, &interval).map(|hit| hit.t);
+            let actual = bvh.hit(&ray, &interval).map(|hit| hit.t);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn empty_list_has_no_bvh() {
+        assert!(BvhNode::new(HittableList::new()).is_none());
+    }
+}
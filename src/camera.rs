@@ -1,7 +1,120 @@
 use crate::color::*;
 use crate::geometry::*;
+use crate::materials::Scatter;
 use crate::maths::*;
-use crate::scene::*;
+use crate::pdf::{power_heuristic, EnvironmentPdf, HittablePdf, MixturePdf, Pdf};
+use crate::random::{random_double, random_double_range, random_vec3_in_unit_disk};
+use crate::scene::World;
+
+const MIN_T: f64 = 0.0001; // minimum t of hits along a ray, to avoid self-intersection
+
+// How long the paths of light get. After `roulette_depth` bounces, paths end at random, the
+// more likely the less light they still carry, which saves time on paths that hardly matter
+// without changing the average. `max_depth` optionally cuts off all longer paths, which is
+// faster but loses their light.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DepthPolicy {
+    pub roulette_depth: usize,
+    pub max_depth: Option<usize>,
+}
+
+impl Default for DepthPolicy {
+    fn default() -> Self {
+        Self {
+            roulette_depth: 3,
+            max_depth: None,
+        }
+    }
+}
+
+impl DepthPolicy {
+    // whether a path may have the bounce with the index, counting from zero
+    pub fn continues(&self, bounce: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| bounce < max_depth)
+    }
+}
+
+// the user facing camera parameters, as stored in a scene
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CameraSettings {
+    pub look_from: Point, // position of the camera
+    pub look_at: Point,   // point the camera looks at
+    pub vup: Vec3,        // "up" direction of the camera, does not need to be orthogonal
+    pub vfov: f64,        // vertical field of view in degrees
+    // variation angle in degrees of rays through each pixel, 0 is a pinhole camera
+    pub defocus_angle: f64,
+    pub focus_distance: f64, // distance from look_from to the plane that is in focus
+    // the shutter is open between these times in [0, 1], moving objects are blurred over
+    // that part of their motion
+    pub shutter_open: f64,
+    pub shutter_close: f64,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            look_from: Point::new(0.0, 0.0, 0.0),
+            look_at: Point::new(0.0, 0.0, -1.0),
+            vup: Vec3::new(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            defocus_angle: 0.0,
+            focus_distance: 1.0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+// interactive camera movements
+impl CameraSettings {
+    // rotates the camera around the look_at point, yaw around vup and pitch around
+    // the horizontal camera axis, both in degrees
+    pub fn orbit(&mut self, yaw: f64, pitch: f64) {
+        let up = self.vup.normalized();
+        let mut offset = self.look_from - self.look_at;
+        offset = offset.rotated(up, degrees_to_radians(yaw));
+
+        // stop a bit before looking straight up or down, where vup becomes degenerate
+        let max_elevation = 89.0;
+        let elevation = radians_to_degrees((offset.normalized().dot(up)).clamp(-1.0, 1.0).asin());
+        let pitch = (elevation + pitch).clamp(-max_elevation, max_elevation) - elevation;
+        let right = up.cross(offset).normalized();
+        offset = offset.rotated(right, degrees_to_radians(-pitch));
+
+        self.look_from = self.look_at + offset;
+    }
+
+    // moves the camera towards (positive) or away from the look_at point
+    pub fn dolly(&mut self, distance: f64) {
+        let offset = self.look_from - self.look_at;
+        let min_distance = 0.01;
+        let new_distance = (offset.length() - distance).max(min_distance);
+        self.look_from = self.look_at + offset.normalized() * new_distance;
+    }
+
+    // sets the focus distance to the surface in the center of the view,
+    // returns false if there is nothing to focus on
+    pub fn autofocus(&mut self, world: &dyn Hittable) -> bool {
+        let ray = Ray::new(self.look_from, self.look_at - self.look_from);
+        match world.hit(&ray, &Interval::new(0.0001, f64::INFINITY)) {
+            Some(hit) => {
+                self.focus_distance = hit.t * ray.direction.length();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // moves camera and look_at point together, sideways (right) and vertically (up)
+    pub fn strafe(&mut self, right: f64, up: f64) {
+        let w = (self.look_from - self.look_at).normalized();
+        let u = self.vup.cross(w).normalized();
+        let v = w.cross(u);
+        let offset = u * right + v * up;
+        self.look_from += offset;
+        self.look_at += offset;
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
@@ -12,29 +125,51 @@ pub struct Camera {
     pixel00_loc: Vec3,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
+    defocus_angle: f64,
+    defocus_disk_u: Vec3, // horizontal radius of the lens disk
+    defocus_disk_v: Vec3, // vertical radius of the lens disk
+    shutter_open: f64,
+    shutter_close: f64,
+    // orthonormal camera basis: u points right, v up and w opposite the view direction
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
 }
 
 impl Camera {
+    // pixel_aspect_ratio is the width of a pixel divided by its height, which is
+    // not 1 for terminal cells
     pub fn new(
         pixel_width: f64,
         pixel_height: f64,
         pixel_aspect_ratio: f64,
-        focal_length: f64,
-        origin: Vec3,
+        settings: &CameraSettings,
     ) -> Self {
-        let viewport_height = 2.0;
-        let viewport_width = viewport_height * pixel_width / pixel_height;
-        let viewport_u = Vec3::new(viewport_width, 0.0, 0.0) * pixel_aspect_ratio;
-        let viewport_v = Vec3::new(0.0, -viewport_height, 0.0);
+        let origin = settings.look_from;
+        // the viewport is placed in the focus plane, so that the rays through the pixel
+        // centers from any point on the lens converge there
+        let focal_length = settings.focus_distance;
+
+        let theta = degrees_to_radians(settings.vfov);
+        let viewport_height = 2.0 * (theta / 2.0).tan() * focal_length;
+        let viewport_width = viewport_height * pixel_width / pixel_height * pixel_aspect_ratio;
+
+        let w = (settings.look_from - settings.look_at).normalized();
+        let u = settings.vup.cross(w).normalized();
+        let v = w.cross(u);
+
+        let viewport_u = u * viewport_width;
+        let viewport_v = -v * viewport_height;
 
         // horizontal and vertical delta vectors
         let pixel_delta_u = viewport_u / pixel_width;
         let pixel_delta_v = viewport_v / pixel_height;
 
         // location of the upper left pixel
-        let viewport_upper_left =
-            origin - Vec3::new(0.0, 0.0, focal_length) - viewport_u / 2.0 - viewport_v / 2.0;
+        let viewport_upper_left = origin - w * focal_length - viewport_u / 2.0 - viewport_v / 2.0;
         let pixel00_loc = viewport_upper_left + pixel_delta_u / 2.0 + pixel_delta_v / 2.0;
+
+        let defocus_radius = focal_length * degrees_to_radians(settings.defocus_angle / 2.0).tan();
         Self {
             pixel_width,
             pixel_height,
@@ -43,6 +178,14 @@ impl Camera {
             pixel00_loc,
             pixel_delta_u,
             pixel_delta_v,
+            defocus_angle: settings.defocus_angle,
+            defocus_disk_u: u * defocus_radius,
+            defocus_disk_v: v * defocus_radius,
+            shutter_open: settings.shutter_open,
+            shutter_close: settings.shutter_close,
+            u,
+            v,
+            w,
         }
     }
 
@@ -50,29 +193,251 @@ impl Camera {
     pub fn get_pixel_ray(&self, x: f64, y: f64) -> Ray {
         let pixel_center = self.pixel00_loc + self.pixel_delta_u * x + self.pixel_delta_v * y;
 
-        // create a ray from the camera origin to the pixel
+        // create a ray from the camera origin, or a random point on the lens, to the pixel
         // direction is intentionally not normalized
-        Ray::new(self.origin, pixel_center - self.origin)
+        let ray_origin = if self.defocus_angle <= 0.0 {
+            self.origin
+        } else {
+            self.defocus_disk_sample()
+        };
+        Ray::with_time(ray_origin, pixel_center - ray_origin, self.sample_time())
     }
 
-    pub fn ray_color(&self, ray: &Ray, depth: i32, world: &HittableList) -> Color {
-        let min_t = 0.0001; // minimum t to avoid self-intersection
-        if depth <= 0 {
+    // a random moment while the shutter is open
+    fn sample_time(&self) -> f64 {
+        if self.shutter_close <= self.shutter_open {
+            self.shutter_open
+        } else {
+            random_double_range(self.shutter_open, self.shutter_close)
+        }
+    }
+
+    fn defocus_disk_sample(&self) -> Point {
+        let p = random_vec3_in_unit_disk();
+        self.origin + self.defocus_disk_u * p.x + self.defocus_disk_v * p.y
+    }
+
+    // Follows the path of light backwards from the ray through the scene. The path is
+    // traced in a loop, so that long paths, e.g. inside glass, cannot overflow the stack.
+    pub fn ray_color(&self, ray: &Ray, depth: &DepthPolicy, world: &World) -> Color {
+        let mut ray = *ray;
+        let mut color = Color::default();
+        // share of the light at the current hit that makes it back to the camera
+        let mut throughput = Color::new(1.0, 1.0, 1.0);
+        // scales the light that the ray finds on an emissive surface. It is below one after
+        // diffuse bounces, where sampling the lights could have found the same light, so
+        // that the two ways to find it are combined instead of counted twice.
+        let mut emission_weight = 1.0;
+
+        for bounce in 0.. {
+            let Some(hit) = world
+                .objects
+                .hit(&ray, &Interval::new(MIN_T, f64::INFINITY))
+            else {
+                let background = world.background.color(ray.direction);
+                color += throughput * background * emission_weight;
+                break;
+            };
+            if let Some(fog) = &world.fog {
+                let transmittance = fog.transmittance(hit.t * ray.direction.length());
+                color += throughput * fog.color * (1.0 - transmittance);
+                throughput = throughput * transmittance;
+            }
+            color += throughput * hit.material.emitted(&hit) * emission_weight;
+
+            let Some(scatter) = hit.material.scatter(&ray, &hit) else {
+                break;
+            };
+            // light found through a light sample counts as the next bounce as well
+            if !depth.continues(bounce + 1) {
+                break;
+            }
+            throughput = throughput * scatter.attenuation;
+            if bounce + 1 >= depth.roulette_depth {
+                // the brighter the rest of the path can get, the more likely it survives.
+                // Survivors make up for the others, so the result stays the same on average.
+                // Below one, so that even paths that lose nothing end eventually.
+                let survival = throughput.x.max(throughput.y).max(throughput.z).min(0.95);
+                if random_double() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+
+            match scatter.scatter {
+                Scatter::Specular(scattered_ray) => {
+                    ray = scattered_ray;
+                    emission_weight = 1.0;
+                }
+                Scatter::Diffuse(pdf) => {
+                    let direction = match light_pdf(world, hit.point, ray.time) {
+                        Some(lights) => {
+                            color += throughput
+                                * self.sample_light(
+                                    &hit,
+                                    ray.time,
+                                    pdf.as_ref(),
+                                    lights.as_ref(),
+                                    world,
+                                );
+                            let direction = pdf.generate();
+                            emission_weight =
+                                power_heuristic(pdf.value(direction), lights.value(direction));
+                            direction
+                        }
+                        None => {
+                            emission_weight = 1.0;
+                            pdf.generate()
+                        }
+                    };
+                    ray = Ray::with_time(hit.point, direction, ray.time);
+                }
+            }
+        }
+        color
+    }
+
+    // The light that arrives at a diffuse hit from one random direction towards the lights,
+    // weighted for multiple importance sampling with the material's own samples.
+    fn sample_light(
+        &self,
+        hit: &HitRecord,
+        time: f64,
+        pdf: &dyn Pdf,
+        lights: &dyn Pdf,
+        world: &World,
+    ) -> Color {
+        let direction = lights.generate();
+        let light_pdf = lights.value(direction);
+        let scatter_pdf = pdf.value(direction);
+        if light_pdf <= 0.0 || scatter_pdf <= 0.0 {
             return Color::default();
         }
-        match world.hit(&ray, &Interval::new(min_t, f64::INFINITY)) {
-            Some(hit) => match hit.material.scatter(&ray, &hit) {
-                Some(scatter) => {
-                    scatter.attenuation * self.ray_color(&scatter.scattered_ray, depth - 1, world)
+
+        let shadow_ray = Ray::with_time(hit.point, direction, time);
+        let interval = Interval::new(MIN_T, f64::INFINITY);
+        let light = match world.objects.hit(&shadow_ray, &interval) {
+            Some(light_hit) => {
+                let mut light = light_hit.material.emitted(&light_hit);
+                if let Some(fog) = &world.fog {
+                    light = light * fog.transmittance(light_hit.t * direction.length());
                 }
-                None => Color::default(),
-            },
-            None => {
-                // background: lerp from white to blue
-                let unit_direction = ray.direction.normalized();
-                let a = 0.5 * (unit_direction.y + 1.0);
-                Vec3::lerp(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0), a)
+                light
             }
+            None => world.background.color(direction),
+        };
+        light * (scatter_pdf * power_heuristic(light_pdf, scatter_pdf) / light_pdf)
+    }
+}
+
+// The density of the light samples at a point: towards the lights of the scene, the bright
+// parts of the environment, or both. None if there is nothing to sample.
+fn light_pdf(world: &World, origin: Point, time: f64) -> Option<Box<dyn Pdf + '_>> {
+    let surfaces = (world.lights.object_count() > 0)
+        .then(|| Box::new(HittablePdf::new(&world.lights, origin, time)) as Box<dyn Pdf>);
+    let environment = world
+        .background
+        .is_sampled()
+        .then(|| Box::new(EnvironmentPdf::new(world.background.as_ref())) as Box<dyn Pdf>);
+    match (surfaces, environment) {
+        (Some(surfaces), Some(environment)) => {
+            Some(Box::new(MixturePdf::new(surfaces, environment)))
         }
+        (surfaces, environment) => surfaces.or(environment),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_format::parse_scene;
+
+    // a gray floor under a white sky reflects half of the sky, whichever way the paths end
+    #[test]
+    fn russian_roulette_keeps_the_average() {
+        let scene = parse_scene(
+            "camera look_from 0 1 0 look_at 0 0 -1
+             background solid color 1 1 1
+             material gray lambertian albedo 0.5 0.5 0.5
+             plane point 0 0 0 normal 0 1 0 material gray",
+        )
+        .unwrap();
+        let world = World::new(&scene).unwrap();
+        let camera = Camera::new(1.0, 1.0, 1.0, &scene.camera);
+        let ray = camera.get_pixel_ray(0.5, 0.5);
+        let mean = |depth: DepthPolicy| {
+            let samples = 20_000;
+            let sum = (0..samples).fold(Color::default(), |sum, _| {
+                sum + camera.ray_color(&ray, &depth, &world)
+            });
+            sum.x / samples as f64
+        };
+
+        let full = DepthPolicy {
+            roulette_depth: usize::MAX,
+            max_depth: None,
+        };
+        assert!((mean(full) - 0.5).abs() < 1e-9);
+        let roulette = DepthPolicy {
+            roulette_depth: 0,
+            max_depth: None,
+        };
+        assert!((mean(roulette) - 0.5).abs() < 0.02);
+        // a single bounce ends the path on the floor, which does not glow itself
+        let one_bounce = DepthPolicy {
+            roulette_depth: usize::MAX,
+            max_depth: Some(1),
+        };
+        assert_eq!(mean(one_bounce), 0.0);
+    }
+
+    // a black wall behind fog shows only the fog, rays that leave the scene see the
+    // background without it
+    #[test]
+    fn fog_fades_surfaces_but_not_the_background() {
+        let scene = parse_scene(
+            "camera look_from 0 0 0 look_at 0 0 -1
+             background solid color 0.5 0.5 0.5
+             fog density 0.5 color 1 1 1
+             material black lambertian albedo 0 0 0
+             plane point 0 0 -2 normal 0 0 1 material black",
+        )
+        .unwrap();
+        let world = World::new(&scene).unwrap();
+        let camera = Camera::new(1.0, 1.0, 1.0, &scene.camera);
+        let depth = DepthPolicy::default();
+        // the wall is 2 units in front of the camera
+        let fogged = camera.ray_color(&camera.get_pixel_ray(0.0, 0.0), &depth, &world);
+        let expected = 1.0 - (-1.0_f64).exp();
+        assert!((fogged - Color::new(expected, expected, expected)).length() < 1e-9);
+
+        let away = Ray::new(Point::zero(), Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(
+            camera.ray_color(&away, &depth, &world),
+            Color::new(0.5, 0.5, 0.5)
+        );
+    }
+
+    // a gray ball inside a glowing sphere reflects half of the light that comes from every
+    // direction, which also tests sampling a light from inside of it
+    #[test]
+    fn furnace_inside_a_light_reflects_the_albedo() {
+        let scene = parse_scene(
+            "camera look_from 0 0 3 look_at 0 0 0
+             material gray lambertian albedo 0.5 0.5 0.5
+             material glow diffuse_light emit 1 1 1
+             sphere center 0 0 0 radius 1 material gray
+             sphere center 0 0 0 radius 5 material glow",
+        )
+        .unwrap();
+        let world = World::new(&scene).unwrap();
+        let camera = Camera::new(1.0, 1.0, 1.0, &scene.camera);
+        // the center of the only pixel, looking straight at the ball
+        let ray = camera.get_pixel_ray(0.0, 0.0);
+        let samples = 20_000;
+        let sum = (0..samples).fold(Color::default(), |sum, _| {
+            sum + camera.ray_color(&ray, &DepthPolicy::default(), &world)
+        });
+        assert!((sum.x / samples as f64 - 0.5).abs() < 0.02);
     }
 }
@@ -0,0 +1,278 @@
+use crate::camera::DepthPolicy;
+use crate::image::ImageFormat;
+use crate::renderer::RenderParameters;
+use crate::tonemap::{ToneMapOperator, ToneMapping};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage:
+  rats [SCENE]                 interactive terminal renderer
+  rats render [OPTIONS] -o OUT render without a terminal and write an image file
+  rats ansi [OPTIONS] [-o OUT] render terminal art, streamed to stdout or written to a file
+
+render options:
+  --scene FILE     scene file to render, the built-in demo scene if not given
+  --width N        image width in pixels (default 800)
+  --height N       image height in pixels (default 450)
+  --spp N          samples per pixel (default 256)
+  -o, --output OUT output image, the extension selects the format: .ppm, .png, .pfm or .hdr
+
+ansi options:
+  --scene FILE     scene file to render, the built-in demo scene if not given
+  --width N        width in terminal cells (default 192)
+  --height N       height in terminal cells (default 72)
+  --spp N          samples per terminal cell, rounded up to a multiple of 8 (default 512)
+  -o, --output OUT output file, .html writes a web page, anything else ANSI escape codes
+
+path options, for both:
+  --roulette-depth N  bounces before paths may end at random (default 3), which does not
+                      change the image on average
+  --max-depth N       cut off paths after N bounces, faster but darker (default no limit)
+
+display options, for ansi and the .ppm and .png images of render:
+  --exposure EV       brightens the image by EV stops, or darkens it if negative (default 0)
+  --tone-map OP       maps bright colors into range: clamp, reinhard or aces (default clamp)
+  --auto-exposure     exposes the image to middle gray, --exposure then adjusts from there;
+                      ansi output is only printed once the whole image is done
+";
+
+// what the binary was asked to do
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Interactive { scene: Option<PathBuf> },
+    Render(RenderOptions),
+    Ansi(AnsiOptions),
+    Help,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub scene: Option<PathBuf>,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub depth: DepthPolicy,
+    pub tone_mapping: ToneMapping,
+    pub output: PathBuf,
+}
+
+// sizes are in terminal cells, without an output the rows are streamed to stdout
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnsiOptions {
+    pub scene: Option<PathBuf>,
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub depth: DepthPolicy,
+    pub tone_mapping: ToneMapping,
+    pub output: Option<PathBuf>,
+}
+
+// the options shared by the non-interactive commands, None if not given
+#[derive(Debug, Default)]
+struct Options {
+    scene: Option<PathBuf>,
+    width: Option<usize>,
+    height: Option<usize>,
+    samples_per_pixel: Option<usize>,
+    roulette_depth: Option<usize>,
+    max_depth: Option<usize>,
+    exposure: Option<f64>,
+    tone_map: Option<ToneMapOperator>,
+    auto_exposure: bool,
+    output: Option<PathBuf>,
+}
+
+impl Options {
+    fn depth(&self) -> DepthPolicy {
+        let defaults = DepthPolicy::default();
+        DepthPolicy {
+            roulette_depth: self.roulette_depth.unwrap_or(defaults.roulette_depth),
+            max_depth: self.max_depth.or(defaults.max_depth),
+        }
+    }
+
+    fn tone_mapping(&self) -> ToneMapping {
+        let defaults = ToneMapping::default();
+        ToneMapping {
+            operator: self.tone_map.unwrap_or(defaults.operator),
+            exposure: self.exposure.unwrap_or(defaults.exposure),
+            auto_exposure: self.auto_exposure,
+        }
+    }
+}
+
+// parses the command line arguments, without the name of the binary
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    match args.next().as_deref() {
+        None => Ok(Command::Interactive { scene: None }),
+        Some("-h" | "--help" | "help") => Ok(Command::Help),
+        Some("render") => parse_render_options(parse_options(args)?).map(Command::Render),
+        Some("ansi") => Ok(Command::Ansi(parse_ansi_options(parse_options(args)?))),
+        Some(option) if option.starts_with('-') => Err(format!("unknown option {}", option)),
+        Some(scene) => match args.next() {
+            None => Ok(Command::Interactive {
+                scene: Some(PathBuf::from(scene)),
+            }),
+            Some(extra) => Err(format!("unexpected argument {}", extra)),
+        },
+    }
+}
+
+fn parse_options(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(option) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", option))
+        };
+        match option.as_str() {
+            "--scene" => options.scene = Some(PathBuf::from(value()?)),
+            "--width" => options.width = Some(parse_count(&option, &value()?)?),
+            "--height" => options.height = Some(parse_count(&option, &value()?)?),
+            "--spp" => options.samples_per_pixel = Some(parse_count(&option, &value()?)?),
+            "--roulette-depth" => options.roulette_depth = Some(parse_count(&option, &value()?)?),
+            "--max-depth" => options.max_depth = Some(parse_count(&option, &value()?)?),
+            "--exposure" => {
+                let value = value()?;
+                let exposure = value.parse::<f64>().ok().filter(|ev| ev.is_finite());
+                let exposure = exposure
+                    .ok_or_else(|| format!("{} expects a number, got {}", option, value))?;
+                options.exposure = Some(exposure);
+            }
+            "--tone-map" => {
+                let value = value()?;
+                let operator = ToneMapOperator::from_name(&value).ok_or_else(|| {
+                    format!("{} expects clamp, reinhard or aces, got {}", option, value)
+                })?;
+                options.tone_map = Some(operator);
+            }
+            "--auto-exposure" => options.auto_exposure = true,
+            "-o" | "--output" => options.output = Some(PathBuf::from(value()?)),
+            _ => return Err(format!("unknown option {}", option)),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_render_options(options: Options) -> Result<RenderOptions, String> {
+    let depth = options.depth();
+    let tone_mapping = options.tone_mapping();
+    let output = options
+        .output
+        .ok_or("render needs an output file, set it with -o")?;
+    // check the format before rendering, not after
+    ImageFormat::from_path(&output).map_err(|error| error.to_string())?;
+    Ok(RenderOptions {
+        scene: options.scene,
+        width: options.width.unwrap_or(800),
+        height: options.height.unwrap_or(450),
+        samples_per_pixel: options.samples_per_pixel.unwrap_or(256),
+        depth,
+        tone_mapping,
+        output,
+    })
+}
+
+fn parse_ansi_options(options: Options) -> AnsiOptions {
+    let defaults = RenderParameters::default();
+    let depth = options.depth();
+    let tone_mapping = options.tone_mapping();
+    AnsiOptions {
+        scene: options.scene,
+        width: options.width.unwrap_or(defaults.width),
+        height: options.height.unwrap_or(defaults.height),
+        samples_per_pixel: options
+            .samples_per_pixel
+            .unwrap_or(defaults.samples_per_pixel),
+        depth,
+        tone_mapping,
+        output: options.output,
+    }
+}
+
+fn parse_count(option: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(count) if count > 0 => Ok(count),
+        _ => Err(format!(
+            "{} expects a positive number, got {}",
+            option, value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_commands() {
+        let command = parse_args(args(
+            "render --scene s.txt --width 80 --height 45 --spp 16 -o out.png",
+        ));
+        assert_eq!(
+            command,
+            Ok(Command::Render(RenderOptions {
+                scene: Some(PathBuf::from("s.txt")),
+                width: 80,
+                height: 45,
+                samples_per_pixel: 16,
+                depth: DepthPolicy::default(),
+                tone_mapping: ToneMapping::default(),
+                output: PathBuf::from("out.png"),
+            }))
+        );
+        assert_eq!(
+            parse_args(args("scene.txt")),
+            Ok(Command::Interactive {
+                scene: Some(PathBuf::from("scene.txt"))
+            })
+        );
+        assert!(parse_args(args("render --width 80")).is_err());
+        assert!(parse_args(args("render --spp 0 -o out.png")).is_err());
+        assert!(parse_args(args("render -o out.jpg")).is_err());
+        assert_eq!(
+            parse_args(args("ansi --width 40")),
+            Ok(Command::Ansi(AnsiOptions {
+                scene: None,
+                width: 40,
+                height: RenderParameters::default().height,
+                samples_per_pixel: RenderParameters::default().samples_per_pixel,
+                depth: DepthPolicy::default(),
+                tone_mapping: ToneMapping::default(),
+                output: None,
+            }))
+        );
+        let Ok(Command::Ansi(options)) = parse_args(args("ansi --roulette-depth 5 --max-depth 20"))
+        else {
+            panic!("expected ansi options");
+        };
+        assert_eq!(
+            options.depth,
+            DepthPolicy {
+                roulette_depth: 5,
+                max_depth: Some(20)
+            }
+        );
+        let Ok(Command::Ansi(options)) =
+            parse_args(args("ansi --exposure -1.5 --tone-map aces --auto-exposure"))
+        else {
+            panic!("expected ansi options");
+        };
+        assert_eq!(
+            options.tone_mapping,
+            ToneMapping {
+                operator: ToneMapOperator::Aces,
+                exposure: -1.5,
+                auto_exposure: true,
+            }
+        );
+        assert!(parse_args(args("ansi --tone-map filmic")).is_err());
+        assert!(parse_args(args("ansi --exposure bright")).is_err());
+    }
+}
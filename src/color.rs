@@ -1,39 +1,62 @@
-use crate::maths::Interval;
+//! Colors and the conversions between color spaces, which all go through palette.
+//!
+//! The renderer works with linear RGB colors in its working space, see WorkingSpace. Scene
+//! files, 8 bit images and terminals use sRGB, so colors are converted at these edges.
+
+use crate::maths::{Interval, Vec3};
+use palette::convert::FromColorUnclamped;
+use palette::encoding::Linear;
+use palette::matrix::Mat3;
+use palette::rgb::{Primaries, Rgb, RgbSpace};
+use palette::white_point::{Any, D65};
+use palette::{LinSrgb, Oklab, Srgb, Xyz, Yxy};
+
+// a linear RGB color in the working space
 pub type Color = crate::maths::Vec3;
 
 impl Color {
-    pub fn to_color(&self) -> ratatui::prelude::Color {
-        let intensity = Interval {
-            min: 0.0,
-            max: 0.999,
-        };
-        ratatui::prelude::Color::Rgb(
-            (256.0 * intensity.clamp(self.x)) as u8,
-            (256.0 * intensity.clamp(self.y)) as u8,
-            (256.0 * intensity.clamp(self.z)) as u8,
-        )
+    // encodes a linear sRGB color for the terminal
+    pub fn to_color(self) -> ratatui::prelude::Color {
+        let [r, g, b] = self.to_srgb8();
+        ratatui::prelude::Color::Rgb(r, g, b)
     }
 
-    pub fn saturated(&self) -> Color {
-        Color {
-            x: self.x.clamp(0.0, 1.0),
-            y: self.y.clamp(0.0, 1.0),
-            z: self.z.clamp(0.0, 1.0),
-        }
+    // encodes a linear sRGB color with the sRGB transfer function as 8 bit values, colors
+    // outside of [0, 1] are clamped
+    pub fn to_srgb8(self) -> [u8; 3] {
+        let linear = self.saturated();
+        let linear = LinSrgb::new(linear.x, linear.y, linear.z);
+        let encoded: Srgb<u8> = Srgb::from_linear(linear);
+        [encoded.red, encoded.green, encoded.blue]
     }
 
-    pub fn gamma_correction(color: &Color) -> Color {
-        Color {
-            x: color.x.sqrt(),
-            y: color.y.sqrt(),
-            z: color.z.sqrt(),
-        }
+    // decodes an sRGB encoded color, e.g. from an image file, into linear sRGB
+    pub fn from_srgb(encoded: Color) -> Color {
+        let encoded = Srgb::new(encoded.x, encoded.y, encoded.z);
+        from_palette(encoded.into_linear())
+    }
+
+    // the OKLab coordinates (L, a, b) of a linear sRGB color, in which distances follow how
+    // different colors look, also between dark colors
+    pub fn to_oklab(self) -> Vec3 {
+        let lab = Oklab::from_color_unclamped(LinSrgb::new(self.x, self.y, self.z));
+        Vec3::new(lab.l, lab.a, lab.b)
+    }
+
+    pub fn saturated(&self) -> Color {
+        let unit = Interval::new(0.0, 1.0);
+        Color::new(unit.clamp(self.x), unit.clamp(self.y), unit.clamp(self.z))
     }
 
     pub fn brightness(&self) -> f64 {
         0.299 * self.x + 0.587 * self.y + 0.114 * self.z
     }
 
+    // the relative luminance of a linear color with the Rec. 709 primaries
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn black() -> Color {
         Color::new(0.0, 0.0, 0.0)
     }
@@ -44,3 +67,159 @@ impl Color {
         Color::new(1.0, 0.0, 1.0)
     }
 }
+
+fn from_palette<S>(color: Rgb<S, f64>) -> Color {
+    Color::new(color.red, color.green, color.blue)
+}
+
+// The RGB primaries that the renderer computes with. All of them use the D65 white point,
+// so white stays (1, 1, 1). Products of colors, e.g. light that bounces off several colored
+// surfaces, come out differently in different spaces. A wider gamut keeps saturated colors
+// apart that sRGB would clip, and is closer to the spectra of real light.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WorkingSpace {
+    #[default]
+    Srgb,
+    Rec2020,
+}
+
+type LinRec2020 = Rgb<Linear<Rec2020>, f64>;
+
+impl WorkingSpace {
+    pub const ALL: [WorkingSpace; 2] = [WorkingSpace::Srgb, WorkingSpace::Rec2020];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WorkingSpace::Srgb => "srgb",
+            WorkingSpace::Rec2020 => "rec2020",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|space| space.name() == name)
+    }
+
+    // converts a linear sRGB color, e.g. from a scene file or an image, to the working space
+    pub fn convert_srgb(self, color: Color) -> Color {
+        match self {
+            WorkingSpace::Srgb => color,
+            WorkingSpace::Rec2020 => from_palette(LinRec2020::from_color_unclamped(LinSrgb::new(
+                color.x, color.y, color.z,
+            ))),
+        }
+    }
+
+    // converts a color of the working space to linear sRGB, e.g. for a display. Colors
+    // outside of the sRGB gamut get negative components
+    pub fn to_srgb(self, color: Color) -> Color {
+        match self {
+            WorkingSpace::Srgb => color,
+            WorkingSpace::Rec2020 => from_palette(LinSrgb::from_color_unclamped(LinRec2020::new(
+                color.x, color.y, color.z,
+            ))),
+        }
+    }
+
+    // converts CIE XYZ tristimulus values, e.g. of a sky model, to the working space
+    pub fn convert_xyz(self, xyz: Xyz<D65, f64>) -> Color {
+        match self {
+            WorkingSpace::Srgb => from_palette(LinSrgb::<f64>::from_color_unclamped(xyz)),
+            WorkingSpace::Rec2020 => from_palette(LinRec2020::from_color_unclamped(xyz)),
+        }
+    }
+}
+
+// the primaries of ITU-R BT.2020 for ultra HD video, which cover most surface colors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rec2020;
+
+impl Primaries<f64> for Rec2020 {
+    fn red() -> Yxy<Any, f64> {
+        Yxy::new(0.708, 0.292, 0.2627217174)
+    }
+    fn green() -> Yxy<Any, f64> {
+        Yxy::new(0.170, 0.797, 0.6779892755)
+    }
+    fn blue() -> Yxy<Any, f64> {
+        Yxy::new(0.131, 0.046, 0.0592890071)
+    }
+}
+
+impl RgbSpace for Rec2020 {
+    type Primaries = Rec2020;
+    type WhitePoint = D65;
+
+    // the matrices would otherwise be derived from the primaries for every conversion
+    fn rgb_to_xyz_matrix() -> Option<Mat3<f64>> {
+        Some([
+            0.6370101914,
+            0.1446150274,
+            0.1688447812, //
+            0.2627217174,
+            0.6779892755,
+            0.0592890071, //
+            0.0000000000,
+            0.0280723288,
+            1.0607576712,
+        ])
+    }
+
+    fn xyz_to_rgb_matrix() -> Option<Mat3<f64>> {
+        Some([
+            1.7165106698,
+            -0.3556416700,
+            -0.2533455418, //
+            -0.6666930012,
+            1.6165022083,
+            0.0157687504, //
+            0.0176436388,
+            -0.0427797817,
+            0.9423050727,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Color, b: Color) {
+        assert!((a - b).length() < 1e-4, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(Color::white().to_srgb8(), [255, 255, 255]);
+        assert_eq!(Color::new(-1.0, 0.0, 2.0).to_srgb8(), [0, 0, 255]);
+        // middle gray is encoded far brighter than half
+        assert_eq!(Color::new(0.18, 0.18, 0.18).to_srgb8(), [118, 118, 118]);
+        // the linear part near black
+        assert_near(
+            Color::from_srgb(Color::new(0.02, 0.02, 0.02)),
+            Color::new(0.02, 0.02, 0.02) / 12.92,
+        );
+        let color = Color::new(0.25, 0.5, 0.75);
+        let [r, g, b] = color.to_srgb8();
+        let decoded = Color::from_srgb(Color::new(r as f64, g as f64, b as f64) / 255.0);
+        assert!((decoded - color).length() < 0.01);
+    }
+
+    #[test]
+    fn working_spaces_convert_back_and_forth() {
+        let space = WorkingSpace::Rec2020;
+        assert_near(space.convert_srgb(Color::white()), Color::white());
+        let red = Color::new(1.0, 0.0, 0.0);
+        let wide_red = space.convert_srgb(red);
+        assert_near(wide_red, Color::new(0.6274, 0.0691, 0.0164));
+        assert_near(space.to_srgb(wide_red), red);
+        // the pure red of Rec. 2020 lies outside of sRGB
+        let srgb = space.to_srgb(red);
+        assert!(srgb.x > 1.0 && srgb.y < 0.0);
+        // the white point in XYZ is white in every space
+        let white = Xyz::new(0.95047, 1.0, 1.08883);
+        for space in WorkingSpace::ALL {
+            assert_near(space.convert_xyz(white), Color::white());
+            assert_eq!(WorkingSpace::from_name(space.name()), Some(space));
+        }
+    }
+}
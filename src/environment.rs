@@ -0,0 +1,225 @@
+//! Image based lighting from equirectangular HDR environment maps.
+
+use crate::background::Environment;
+use crate::color::{Color, WorkingSpace};
+use crate::image::HdrImage;
+use crate::maths::*;
+use crate::random::random_double;
+use crate::scene_format::SceneError;
+use std::f64::consts::PI;
+use std::path::Path;
+
+// A panorama around the scene in equirectangular projection: the columns go once around
+// the y axis with the center of the image towards -z, the rows go from straight up at the
+// top to straight down at the bottom.
+// Directions are sampled in proportion to the brightness of the pixels, so that small and
+// bright parts like the sun or studio lights are found quickly.
+pub struct EnvironmentMap {
+    image: HdrImage,
+    rotation: f64, // around the y axis in radians
+    intensity: f64,
+    // cumulative distributions for sampling: one over the rows, and one over the columns
+    // of each row, all normalized to end at one
+    row_cdf: Vec<f64>,
+    column_cdfs: Vec<f64>,
+    // chance to pick each pixel, row by row
+    pixel_probabilities: Vec<f64>,
+}
+
+impl EnvironmentMap {
+    // reads a .hdr, .pfm or any other image that HdrImage can load, its linear sRGB colors
+    // are converted to the working space
+    pub fn load(
+        path: &Path,
+        rotation_degrees: f64,
+        intensity: f64,
+        space: WorkingSpace,
+    ) -> Result<Self, SceneError> {
+        let image = HdrImage::load(path).map_err(|error| SceneError::InFile {
+            path: path.to_path_buf(),
+            error: Box::new(error.into()),
+        })?;
+        let image = image.map(|pixel| space.convert_srgb(pixel));
+        Ok(Self::new(image, rotation_degrees, intensity))
+    }
+
+    pub fn new(image: HdrImage, rotation_degrees: f64, intensity: f64) -> Self {
+        let (width, height) = (image.width, image.height);
+        // rows near the poles cover less of the sphere
+        let mut weights = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            for x in 0..width {
+                weights.push(image.get_pixel(x, y).brightness().max(0.0) * sin_theta);
+            }
+        }
+        let total: f64 = weights.iter().sum();
+
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut column_cdfs = Vec::with_capacity(width * height);
+        let mut sum = 0.0;
+        for row in weights.chunks_exact(width) {
+            let row_total: f64 = row.iter().sum();
+            let mut row_sum = 0.0;
+            for weight in row {
+                row_sum += weight;
+                column_cdfs.push(if row_total > 0.0 {
+                    row_sum / row_total
+                } else {
+                    0.0
+                });
+            }
+            sum += row_total;
+            row_cdf.push(if total > 0.0 { sum / total } else { 0.0 });
+        }
+        let pixel_probabilities = weights
+            .iter()
+            .map(|weight| if total > 0.0 { weight / total } else { 0.0 })
+            .collect();
+
+        Self {
+            image,
+            rotation: degrees_to_radians(rotation_degrees),
+            intensity,
+            row_cdf,
+            column_cdfs,
+            pixel_probabilities,
+        }
+    }
+
+    // image coordinates in [0, 1] of a direction, and the sine of its angle to the y axis
+    fn direction_to_uv(&self, direction: Vec3) -> (f64, f64, f64) {
+        let local = direction
+            .normalized()
+            .rotated(Vec3::new(0.0, 1.0, 0.0), -self.rotation);
+        let theta = local.y.clamp(-1.0, 1.0).acos();
+        let phi = local.x.atan2(-local.z);
+        (0.5 + phi / (2.0 * PI), theta / PI, theta.sin())
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let (sin_theta, cos_theta) = (v * PI).sin_cos();
+        let (sin_phi, cos_phi) = ((u - 0.5) * 2.0 * PI).sin_cos();
+        Vec3::new(sin_theta * sin_phi, cos_theta, -sin_theta * cos_phi)
+            .rotated(Vec3::new(0.0, 1.0, 0.0), self.rotation)
+    }
+
+    fn pixel(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+        (x, y)
+    }
+}
+
+// the first index whose cumulative probability reaches the value
+fn sample_cdf(cdf: &[f64], value: f64) -> usize {
+    cdf.partition_point(|&probability| probability < value)
+        .min(cdf.len() - 1)
+}
+
+impl Environment for EnvironmentMap {
+    fn color(&self, direction: Vec3) -> Color {
+        let (u, v, _) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel(u, v);
+        self.image.get_pixel(x, y) * self.intensity
+    }
+
+    fn is_sampled(&self) -> bool {
+        self.row_cdf.last().is_some_and(|&total| total > 0.0)
+    }
+
+    fn pdf_value(&self, direction: Vec3) -> f64 {
+        let (u, v, sin_theta) = self.direction_to_uv(direction);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.pixel(u, v);
+        let probability = self.pixel_probabilities[y * self.image.width + x];
+        // a pixel covers 2 pi / width by pi / height of the angles, times sin theta
+        let pixels = (self.image.width * self.image.height) as f64;
+        probability * pixels / (2.0 * PI * PI * sin_theta)
+    }
+
+    fn random(&self) -> Vec3 {
+        let width = self.image.width;
+        let y = sample_cdf(&self.row_cdf, random_double());
+        let x = sample_cdf(
+            &self.column_cdfs[y * width..(y + 1) * width],
+            random_double(),
+        );
+        let u = (x as f64 + random_double()) / width as f64;
+        let v = (y as f64 + random_double()) / self.image.height as f64;
+        self.uv_to_direction(u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a dark panorama with a small bright sun
+    fn sunny_map(rotation: f64) -> EnvironmentMap {
+        let mut image = HdrImage::new(64, 32);
+        for y in 0..32 {
+            for x in 0..64 {
+                image.set_pixel(x, y, Color::new(0.1, 0.1, 0.1));
+            }
+        }
+        image.set_pixel(48, 8, Color::new(10000.0, 10000.0, 10000.0));
+        EnvironmentMap::new(image, rotation, 2.0)
+    }
+
+    #[test]
+    fn directions_map_to_the_panorama() {
+        let map = sunny_map(0.0);
+        for direction in [
+            Vec3::new(0.0, 0.0, -1.0),
+            Vec3::new(0.3, 0.8, 0.1),
+            Vec3::new(-0.5, -0.2, 0.7),
+        ] {
+            let (u, v, _) = map.direction_to_uv(direction);
+            let back = map.uv_to_direction(u, v);
+            assert!((back - direction.normalized()).length() < 1e-9);
+        }
+        // the center of the image is in front, +x to the right of it and up at the top
+        let (u, v, _) = map.direction_to_uv(Vec3::new(0.0, 0.0, -1.0));
+        assert!((u - 0.5).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        let (u, _, _) = map.direction_to_uv(Vec3::new(1.0, 0.0, 0.0));
+        assert!((u - 0.75).abs() < 1e-9);
+        // the sun is at u = 0.75, v = 0.25: to the right and halfway up, scaled by the
+        // intensity
+        let sun = Vec3::new(1.0, 1.0, 0.0);
+        assert_eq!(map.color(sun), Color::new(20000.0, 20000.0, 20000.0));
+        // turned by 90 degrees, the sun moves from +x to -z
+        let turned = sunny_map(90.0);
+        assert_eq!(turned.color(Vec3::new(0.0, 1.0, -1.0)).x, 20000.0);
+    }
+
+    #[test]
+    fn samples_find_the_sun_and_match_the_density() {
+        let map = sunny_map(30.0);
+        assert!(map.is_sampled());
+        let samples = 10_000;
+        let in_sun = (0..samples)
+            .filter(|_| {
+                let direction = map.random();
+                assert!(map.pdf_value(direction) > 0.0);
+                map.color(direction).x > 100.0
+            })
+            .count();
+        assert!(in_sun > samples * 9 / 10);
+
+        // the density integrates to one over the sphere, pixel by pixel
+        let (width, height) = (map.image.width, map.image.height);
+        let mut total = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let v = (y as f64 + 0.5) / height as f64;
+                let direction = map.uv_to_direction((x as f64 + 0.5) / width as f64, v);
+                let solid_angle = 2.0 * PI * PI * (v * PI).sin() / (width * height) as f64;
+                total += map.pdf_value(direction) * solid_angle;
+            }
+        }
+        assert!((total - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,110 @@
+use crate::color::Color;
+use crate::renderer::FrameBuffer;
+use std::fmt::Write;
+
+fn rgb(color: Color) -> (u8, u8, u8) {
+    match color.to_color() {
+        ratatui::prelude::Color::Rgb(r, g, b) => (r, g, b),
+        _ => unreachable!("to_color always returns an rgb color"),
+    }
+}
+
+// One line of the frame as 24-bit ANSI escape sequences followed by the glyphs.
+// Colors are only emitted when they change, and the line ends with a reset so that
+// the terminal colors do not bleed into whatever is printed next.
+pub fn ansi_line(frame: &FrameBuffer, y: usize) -> String {
+    let mut line = String::new();
+    let mut current = None;
+    for x in 0..frame.width {
+        let pixel = frame.get_pixel(x, y);
+        let colors = (rgb(pixel.fg), rgb(pixel.bg));
+        if current != Some(colors) {
+            let ((fr, fg, fb), (br, bg, bb)) = colors;
+            let _ = write!(
+                line,
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                fr, fg, fb, br, bg, bb
+            );
+            current = Some(colors);
+        }
+        line.push(pixel.character);
+    }
+    line.push_str("\x1b[0m");
+    line
+}
+
+// the whole frame as text that can be printed to any terminal with 24-bit color support
+pub fn to_ansi(frame: &FrameBuffer) -> String {
+    let mut text = String::new();
+    for y in 0..frame.height {
+        text.push_str(&ansi_line(frame, y));
+        text.push('\n');
+    }
+    text
+}
+
+// the frame as a standalone web page, runs of equally colored cells share one span
+pub fn to_html(frame: &FrameBuffer) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>rats</title>\n</head>\n\
+         <body style=\"background-color:#000\">\n\
+         <pre style=\"font-family:monospace;line-height:1;letter-spacing:0\">",
+    );
+    for y in 0..frame.height {
+        let mut x = 0;
+        while x < frame.width {
+            let pixel = frame.get_pixel(x, y);
+            let colors = (rgb(pixel.fg), rgb(pixel.bg));
+            let ((fr, fg, fb), (br, bg, bb)) = colors;
+            let _ = write!(
+                html,
+                "<span style=\"color:#{:02x}{:02x}{:02x};background-color:#{:02x}{:02x}{:02x}\">",
+                fr, fg, fb, br, bg, bb
+            );
+            while x < frame.width {
+                let pixel = frame.get_pixel(x, y);
+                if (rgb(pixel.fg), rgb(pixel.bg)) != colors {
+                    break;
+                }
+                match pixel.character {
+                    '<' => html.push_str("&lt;"),
+                    '>' => html.push_str("&gt;"),
+                    '&' => html.push_str("&amp;"),
+                    character => html.push(character),
+                }
+                x += 1;
+            }
+            html.push_str("</span>");
+        }
+        html.push('\n');
+    }
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terminal::TerminalPixel;
+
+    fn two_cell_frame() -> FrameBuffer {
+        let mut frame = FrameBuffer::new(2, 1);
+        *frame.get_pixel_mut(0, 0) = TerminalPixel::new(Color::white(), Color::black(), '⠁');
+        *frame.get_pixel_mut(1, 0) = TerminalPixel::new(Color::white(), Color::black(), '⠈');
+        frame
+    }
+
+    #[test]
+    fn ansi_only_emits_color_changes() {
+        assert_eq!(
+            to_ansi(&two_cell_frame()),
+            "\x1b[38;2;255;255;255m\x1b[48;2;0;0;0m⠁⠈\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn html_merges_equal_colors_into_one_span() {
+        let html = to_html(&two_cell_frame());
+        assert!(html.contains("<span style=\"color:#ffffff;background-color:#000000\">⠁⠈</span>"));
+    }
+}
@@ -1,14 +1,16 @@
+use crate::aabb::Aabb;
 use crate::materials::*;
 
 use crate::maths::*;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct HitRecord {
     pub point: Point,
     pub normal: Vec3, // normal of the hit point, always points against the ray
     pub t: f64,
+    pub uv: Vec2, // surface coordinates of the hit point, for textures
     pub front_face: bool,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl HitRecord {
@@ -17,8 +19,9 @@ impl HitRecord {
         point: Point,
         outward_normal: Vec3,
         t: f64,
+        uv: Vec2,
         ray: &Ray,
-        material: Rc<dyn Material>,
+        material: Arc<dyn Material>,
     ) -> Self {
         let front_face = ray.direction.dot(outward_normal) < 0.0;
         let normal = if front_face {
@@ -31,6 +34,7 @@ impl HitRecord {
             point,
             normal,
             t,
+            uv,
             front_face,
             material,
         }
@@ -38,21 +42,76 @@ impl HitRecord {
 }
 
 // The hittable trait is used to define objects that can be hit by a ray
-pub trait Hittable {
+// Hittables are shared between the render threads, so they need to be Send + Sync
+pub trait Hittable: Send + Sync {
     // The hit method is used to determine if a ray hits the object
     // and if it does, it returns a HitRecord
     fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord>;
+
+    // The bounding box encloses the whole object, it is used to build acceleration structures
+    fn bounding_box(&self) -> Aabb;
+
+    // Objects that can be sampled as lights return random directions from the origin
+    // towards themselves, and the density of those directions per unit solid angle.
+    // The others have a density of zero everywhere.
+    fn pdf_value(&self, _ray: &Ray) -> f64 {
+        0.0
+    }
+
+    fn random(&self, _origin: Point, _time: f64) -> Vec3 {
+        Vec3::new(1.0, 0.0, 0.0)
+    }
+}
+
+// density per unit solid angle of a ray that hits a flat surface at t, when points on the
+// surface are sampled uniformly
+fn area_pdf(ray: &Ray, t: f64, normal: Vec3, area: f64) -> f64 {
+    let length = ray.direction.length();
+    let distance_squared = t * t * length * length;
+    let cosine = (ray.direction.dot(normal) / length).abs();
+    if cosine < 1e-8 {
+        return 0.0;
+    }
+    distance_squared / (cosine * area)
+}
+
+// shared objects, e.g. a mesh that is placed several times, are hittable themselves
+impl<H: Hittable + ?Sized> Hittable for Arc<H> {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        self.as_ref().hit(ray, interval)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.as_ref().bounding_box()
+    }
+
+    fn pdf_value(&self, ray: &Ray) -> f64 {
+        self.as_ref().pdf_value(ray)
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vec3 {
+        self.as_ref().random(origin, time)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Point,
     pub direction: Vec3,
+    pub time: f64, // moment within the shutter interval, moving objects are placed accordingly
 }
 
 impl Ray {
     pub fn new(origin: Point, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self::with_time(origin, direction, 0.0)
+    }
+
+    pub fn with_time(origin: Point, direction: Vec3, time: f64) -> Self {
+        Self {
+            origin,
+            direction,
+            time,
+        }
     }
 
     pub fn at(&self, t: f64) -> Vec3 {
@@ -62,28 +121,56 @@ impl Ray {
 
 #[derive(Clone)]
 pub struct Sphere {
-    pub center: Vec3,
+    pub center: Vec3, // center at time 0
+    pub motion: Vec3, // offset of the center from time 0 to time 1, zero for a static sphere
     pub radius: f64,
-    pub material: Rc<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Point, radius: f64, material: Rc<dyn Material>) -> Self {
+    pub fn new(center: Point, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self::moving(center, center, radius, material)
+    }
+
+    // a sphere that moves linearly from center_start at time 0 to center_end at time 1
+    pub fn moving(
+        center_start: Point,
+        center_end: Point,
+        radius: f64,
+        material: Arc<dyn Material>,
+    ) -> Self {
         Self {
-            center,
+            center: center_start,
+            motion: center_end - center_start,
             radius,
             material,
         }
     }
-    pub fn hit_normal(&self, point_on_surface: Point) -> Vec3 {
-        (point_on_surface - self.center).normalized()
+
+    pub fn center_at(&self, time: f64) -> Point {
+        self.center + self.motion * time
+    }
+
+    pub fn hit_normal(&self, point_on_surface: Point, time: f64) -> Vec3 {
+        (point_on_surface - self.center_at(time)).normalized()
+    }
+
+    // texture coordinates of a point on the unit sphere: u is the angle around the y axis
+    // starting at -x, v the angle from -y to +y, both scaled to [0, 1]
+    pub fn uv(normal: Vec3) -> Vec2 {
+        let theta = (-normal.y).clamp(-1.0, 1.0).acos();
+        let phi = (-normal.z).atan2(normal.x) + std::f64::consts::PI;
+        Vec2::new(
+            phi / (2.0 * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
     }
 }
 
 impl Hittable for Sphere {
     // returns the closest hit t for the ray
     fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
-        let oc = self.center - ray.origin;
+        let oc = self.center_at(ray.time) - ray.origin;
         let a = ray.direction.length_squared();
         let h = ray.direction.dot(oc);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -104,14 +191,564 @@ impl Hittable for Sphere {
         }
 
         let point = ray.at(root);
-        let normal = self.hit_normal(point);
+        let normal = self.hit_normal(point, ray.time);
 
         Some(HitRecord::new(
             point,
             normal,
             root,
+            Sphere::uv(normal),
+            ray,
+            Arc::clone(&self.material),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        // encloses the sphere at both ends of its path, and so everywhere in between
+        let radius = Vec3::new(self.radius, self.radius, self.radius);
+        let start = Aabb::from_points(self.center - radius, self.center + radius);
+        let end_center = self.center_at(1.0);
+        let end = Aabb::from_points(end_center - radius, end_center + radius);
+        Aabb::surrounding(&start, &end)
+    }
+
+    // directions are sampled uniformly in the cone of the sphere as seen from the origin
+    fn pdf_value(&self, ray: &Ray) -> f64 {
+        if self
+            .hit(ray, &Interval::new(0.0001, f64::INFINITY))
+            .is_none()
+        {
+            return 0.0;
+        }
+        let distance_squared = (self.center_at(ray.time) - ray.origin).length_squared();
+        // from inside the sphere `random` samples the whole sphere of directions
+        if distance_squared <= self.radius * self.radius {
+            return 1.0 / (4.0 * std::f64::consts::PI);
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let solid_angle = 2.0 * std::f64::consts::PI * (1.0 - cos_theta_max);
+        if solid_angle <= 0.0 {
+            return 0.0;
+        }
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vec3 {
+        let to_center = self.center_at(time) - origin;
+        let distance_squared = to_center.length_squared();
+        // from inside the sphere every direction hits it, but no cone is visible
+        if distance_squared <= self.radius * self.radius {
+            return crate::random::random_vec3_unit();
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        let z = 1.0 + crate::random::random_double() * (cos_theta_max - 1.0);
+        let phi = 2.0 * std::f64::consts::PI * crate::random::random_double();
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+
+        let axis = to_center.normalized();
+        let (tangent, bitangent) = axis.perpendicular_basis();
+        tangent * (phi.cos() * sin_theta) + bitangent * (phi.sin() * sin_theta) + axis * z
+    }
+}
+
+// A triangle with optional per-vertex normals and texture coordinates,
+// which are interpolated over the surface
+#[derive(Clone)]
+pub struct Triangle {
+    pub vertices: [Point; 3],
+    pub normals: Option<[Vec3; 3]>,
+    pub uvs: Option<[Vec2; 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point; 3], material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Möller–Trumbore intersection, which finds t and the barycentric coordinates at once
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let [p0, p1, p2] = self.vertices;
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+
+        // the ray is parallel to the triangle
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+        let inv_determinant = 1.0 / determinant;
+
+        let s = ray.origin - p0;
+        let b1 = s.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&b1) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let b2 = ray.direction.dot(q) * inv_determinant;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_determinant;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let b0 = 1.0 - b1 - b2;
+        let geometric_normal = edge1.cross(edge2).normalized();
+        let uv = match self.uvs {
+            Some([uv0, uv1, uv2]) => uv0 * b0 + uv1 * b1 + uv2 * b2,
+            None => Vec2::new(b1, b2),
+        };
+
+        let mut record = HitRecord::new(
+            ray.at(t),
+            geometric_normal,
+            t,
+            uv,
+            ray,
+            Arc::clone(&self.material),
+        );
+        // front_face is decided by the geometry, the shading normal is only flipped to match
+        if let Some([n0, n1, n2]) = self.normals {
+            let shading_normal = (n0 * b0 + n1 * b1 + n2 * b2).normalized();
+            record.normal = if shading_normal.dot(record.normal) < 0.0 {
+                -shading_normal
+            } else {
+                shading_normal
+            };
+        }
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let [p0, p1, p2] = self.vertices;
+        // axis-aligned triangles have a flat box, which the slab test would never hit
+        Aabb::surrounding(&Aabb::from_points(p0, p1), &Aabb::from_points(p2, p2)).padded()
+    }
+}
+
+// An infinite plane through a point. Its texture coordinates are the distances along
+// two axes in the plane, so they are not limited to [0, 1]
+#[derive(Clone)]
+pub struct Plane {
+    pub point: Point,
+    pub normal: Vec3,
+    pub material: Arc<dyn Material>,
+    u_axis: Vec3,
+    v_axis: Vec3,
+}
+
+impl Plane {
+    pub fn new(point: Point, normal: Vec3, material: Arc<dyn Material>) -> Self {
+        let normal = normal.normalized();
+        let (u_axis, v_axis) = normal.perpendicular_basis();
+        Self {
+            point,
+            normal,
+            material,
+            u_axis,
+            v_axis,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let denominator = self.normal.dot(ray.direction);
+        // the ray is parallel to the plane
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(self.point - ray.origin) / denominator;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let offset = point - self.point;
+        let uv = Vec2::new(offset.dot(self.u_axis), offset.dot(self.v_axis));
+        Some(HitRecord::new(
+            point,
+            self.normal,
+            t,
+            uv,
+            ray,
+            Arc::clone(&self.material),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::universe()
+    }
+}
+
+// A parallelogram spanned by two edges from a corner, the texture coordinates go from
+// 0 to 1 along each edge
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Point,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Arc<dyn Material>,
+    normal: Vec3,
+    // maps points in the plane to the coordinates along the edges
+    w: Vec3,
+}
+
+impl Quad {
+    pub fn new(corner: Point, u: Vec3, v: Vec3, material: Arc<dyn Material>) -> Self {
+        let n = u.cross(v);
+        Self {
+            corner,
+            u,
+            v,
+            material,
+            normal: n.normalized(),
+            w: n / n.dot(n),
+        }
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let denominator = self.normal.dot(ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(self.corner - ray.origin) / denominator;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let planar = point - self.corner;
+        let alpha = self.w.dot(planar.cross(self.v));
+        let beta = self.w.dot(self.u.cross(planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(HitRecord::new(
+            point,
+            self.normal,
+            t,
+            Vec2::new(alpha, beta),
+            ray,
+            Arc::clone(&self.material),
+        ))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let diagonal = Aabb::from_points(self.corner, self.corner + self.u + self.v);
+        let other_diagonal = Aabb::from_points(self.corner + self.u, self.corner + self.v);
+        Aabb::surrounding(&diagonal, &other_diagonal).padded()
+    }
+
+    // points on the quad are sampled uniformly
+    fn pdf_value(&self, ray: &Ray) -> f64 {
+        match self.hit(ray, &Interval::new(0.0001, f64::INFINITY)) {
+            Some(record) => area_pdf(ray, record.t, self.normal, self.u.cross(self.v).length()),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point, _time: f64) -> Vec3 {
+        let alpha = crate::random::random_double();
+        let beta = crate::random::random_double();
+        self.corner + self.u * alpha + self.v * beta - origin
+    }
+}
+
+// A round disk, the texture coordinates are the distance from the center relative to
+// the radius and the angle around the center as a fraction of a full turn
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Point,
+    pub normal: Vec3,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+    u_axis: Vec3,
+    v_axis: Vec3,
+}
+
+impl Disk {
+    pub fn new(center: Point, normal: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        let normal = normal.normalized();
+        let (u_axis, v_axis) = normal.perpendicular_basis();
+        Self {
+            center,
+            normal,
+            radius,
+            material,
+            u_axis,
+            v_axis,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let denominator = self.normal.dot(ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+        let t = self.normal.dot(self.center - ray.origin) / denominator;
+        if !interval.surrounds(t) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let offset = point - self.center;
+        if offset.length_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        let angle = offset.dot(self.v_axis).atan2(offset.dot(self.u_axis));
+        let uv = Vec2::new(
+            offset.length() / self.radius,
+            angle / (2.0 * std::f64::consts::PI) + 0.5,
+        );
+        Some(HitRecord::new(
+            point,
+            self.normal,
+            t,
+            uv,
             ray,
-            Rc::clone(&self.material),
+            Arc::clone(&self.material),
         ))
     }
+
+    fn bounding_box(&self) -> Aabb {
+        // along each axis, the disk extends by the radius times the sine of the angle
+        // between that axis and the normal
+        let n = self.normal;
+        let extent = Vec3::new(
+            (1.0 - n.x * n.x).max(0.0).sqrt(),
+            (1.0 - n.y * n.y).max(0.0).sqrt(),
+            (1.0 - n.z * n.z).max(0.0).sqrt(),
+        ) * self.radius;
+        Aabb::from_points(self.center - extent, self.center + extent).padded()
+    }
+
+    // points on the disk are sampled uniformly
+    fn pdf_value(&self, ray: &Ray) -> f64 {
+        match self.hit(ray, &Interval::new(0.0001, f64::INFINITY)) {
+            Some(record) => {
+                let area = std::f64::consts::PI * self.radius * self.radius;
+                area_pdf(ray, record.t, self.normal, area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Point, _time: f64) -> Vec3 {
+        let p = crate::random::random_vec3_in_unit_disk() * self.radius;
+        self.center + self.u_axis * p.x + self.v_axis * p.y - origin
+    }
+}
+
+// An axis-aligned box between two opposite corners, made of six quads that face outwards
+#[derive(Clone)]
+pub struct Cuboid {
+    sides: [Quad; 6],
+    bbox: Aabb,
+}
+
+impl Cuboid {
+    pub fn new(a: Point, b: Point, material: Arc<dyn Material>) -> Self {
+        let min = Point::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Point::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+        let dx = Vec3::new(max.x - min.x, 0.0, 0.0);
+        let dy = Vec3::new(0.0, max.y - min.y, 0.0);
+        let dz = Vec3::new(0.0, 0.0, max.z - min.z);
+
+        // the edges are ordered so that u x v points out of the box
+        let side = |corner: Point, u: Vec3, v: Vec3| Quad::new(corner, u, v, Arc::clone(&material));
+        let sides = [
+            side(Point::new(min.x, min.y, max.z), dx, dy),  // front
+            side(Point::new(max.x, min.y, max.z), -dz, dy), // right
+            side(Point::new(max.x, min.y, min.z), -dx, dy), // back
+            side(Point::new(min.x, min.y, min.z), dz, dy),  // left
+            side(Point::new(min.x, max.y, max.z), dx, -dz), // top
+            side(Point::new(min.x, min.y, min.z), dx, dz),  // bottom
+        ];
+        Self {
+            sides,
+            bbox: Aabb::from_points(min, max).padded(),
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let mut closest = *interval;
+        let mut record = None;
+        for side in &self.sides {
+            if let Some(hit) = side.hit(ray, &closest) {
+                closest.max = hit.t;
+                record = Some(hit);
+            }
+        }
+        record
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+    use crate::random::random_vec3_unit;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)))
+    }
+
+    #[test]
+    fn cuboid_normals_face_outwards() {
+        let cuboid = Cuboid::new(
+            Point::new(1.0, 1.0, 1.0),
+            Point::new(-1.0, -1.0, -1.0),
+            material(),
+        );
+        let axes = [
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+        ];
+        for axis in axes.into_iter().flat_map(|axis| [axis, -axis]) {
+            // from outside, the ray hits the side facing it
+            let ray = Ray::new(axis * 3.0, -axis);
+            let record = cuboid
+                .hit(&ray, &Interval::new(0.001, f64::INFINITY))
+                .unwrap();
+            assert!((record.t - 2.0).abs() < 1e-9);
+            assert!(record.front_face);
+            assert_eq!(record.normal, axis);
+
+            // from inside, it hits the back of the opposite side
+            let ray = Ray::new(Point::zero(), axis);
+            let record = cuboid
+                .hit(&ray, &Interval::new(0.001, f64::INFINITY))
+                .unwrap();
+            assert!(!record.front_face);
+            assert_eq!(record.normal, -axis);
+        }
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_the_ray_time() {
+        let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let sphere = Sphere::moving(
+            Point::new(0.0, 0.0, -2.0),
+            Point::new(2.0, 0.0, -2.0),
+            0.5,
+            material,
+        );
+        let interval = Interval::new(0.001, f64::INFINITY);
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let at = |x: f64, time: f64| Ray::with_time(Point::new(x, 0.0, 0.0), forward, time);
+
+        assert!(sphere.hit(&at(0.0, 0.0), &interval).is_some());
+        assert!(sphere.hit(&at(0.0, 1.0), &interval).is_none());
+        let record = sphere.hit(&at(1.0, 0.5), &interval).unwrap();
+        assert!((record.t - 1.5).abs() < 1e-9);
+        assert!((record.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        let bbox = sphere.bounding_box();
+        assert_eq!((bbox.x.min, bbox.x.max), (-0.5, 2.5));
+    }
+
+    #[test]
+    fn flat_shapes_only_hit_inside_their_bounds() {
+        let down = Vec3::new(0.0, -1.0, 0.0);
+        let up = Vec3::new(0.0, 1.0, 0.0);
+        let interval = Interval::new(0.001, f64::INFINITY);
+        let quad = Quad::new(
+            Point::zero(),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            material(),
+        );
+        let disk = Disk::new(Point::zero(), up, 1.0, material());
+        let plane = Plane::new(Point::zero(), up, material());
+
+        let record = quad.hit(&Ray::new(Point::new(1.0, 1.0, 0.25), down), &interval);
+        assert!((record.unwrap().uv - Vec2::new(0.5, 0.25)).length() < 1e-9);
+        assert!(quad
+            .hit(&Ray::new(Point::new(-0.1, 1.0, 0.5), down), &interval)
+            .is_none());
+
+        assert!(disk
+            .hit(&Ray::new(Point::new(0.7, 1.0, 0.7), down), &interval)
+            .is_some());
+        assert!(disk
+            .hit(&Ray::new(Point::new(0.8, 1.0, 0.8), down), &interval)
+            .is_none());
+
+        let record = plane.hit(&Ray::new(Point::new(1e6, 1.0, -1e6), down), &interval);
+        assert!(record.unwrap().front_face);
+        assert!(!plane.bounding_box().is_bounded());
+    }
+
+    #[test]
+    fn light_samples_match_the_light_density() {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let lights: [Box<dyn Hittable>; 3] = [
+            Box::new(Sphere::new(
+                Point::new(0.0, 3.0, 0.0),
+                1.0,
+                material.clone(),
+            )),
+            Box::new(Quad::new(
+                Point::new(-1.0, 2.0, -1.0),
+                Vec3::new(2.0, 0.0, 0.0),
+                Vec3::new(0.0, 0.5, 2.0),
+                material.clone(),
+            )),
+            Box::new(Disk::new(
+                Point::new(1.0, 2.0, 0.0),
+                Vec3::new(-1.0, -1.0, 0.0),
+                0.7,
+                material,
+            )),
+        ];
+        let origin = Point::new(0.0, 0.5, 0.0);
+        for light in &lights {
+            // the solid angle of the light, once from its own samples and once from how many
+            // uniformly random directions hit it
+            let samples = 20_000;
+            let from_light_samples: f64 = (0..samples)
+                .map(|_| {
+                    let ray = Ray::new(origin, light.random(origin, 0.0));
+                    let pdf = light.pdf_value(&ray);
+                    assert!(pdf > 0.0);
+                    1.0 / pdf
+                })
+                .sum::<f64>()
+                / samples as f64;
+            let hits = (0..samples * 10)
+                .filter(|_| light.pdf_value(&Ray::new(origin, random_vec3_unit())) > 0.0)
+                .count();
+            let from_hits = hits as f64 / (samples * 10) as f64 * 4.0 * std::f64::consts::PI;
+            assert!(
+                (from_light_samples - from_hits).abs() < 0.03 * from_hits,
+                "{} != {}",
+                from_light_samples,
+                from_hits
+            );
+        }
+    }
 }
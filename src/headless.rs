@@ -0,0 +1,150 @@
+use crate::cli::{AnsiOptions, RenderOptions};
+use crate::export;
+use crate::image::ImageFormat;
+use crate::renderer::{RenderMode, RenderParameters, Renderer};
+use crate::scene::{Scene, World};
+use crate::terminal::{SUBPIXEL_X, SUBPIXEL_Y};
+use crate::tonemap::auto_exposure;
+use color_eyre::{eyre::WrapErr, Result};
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+// the scene file is optional, without one the built-in demo scene is rendered
+pub fn load_scene(path: Option<&Path>) -> Result<Scene> {
+    match path {
+        Some(path) => {
+            Scene::load(path).wrap_err_with(|| format!("failed to load {}", path.display()))
+        }
+        None => Ok(Scene::default()),
+    }
+}
+
+// builds the objects of the scene, which loads the mesh files it refers to
+pub fn build_world(scene: &Scene) -> Result<Arc<World>> {
+    let world = World::new(scene).wrap_err("failed to build the scene")?;
+    Ok(Arc::new(world))
+}
+
+// renders the scene to completion without a terminal UI and writes the image file,
+// progress is reported on stderr
+pub fn render(options: &RenderOptions) -> Result<()> {
+    let scene = load_scene(options.scene.as_deref())?;
+    let world = build_world(&scene)?;
+
+    // every terminal cell is rendered as SUBPIXEL_X x SUBPIXEL_Y square pixels, so the
+    // renderer works on enough cells to cover the image and crops it around the middle
+    let subpixels = SUBPIXEL_X * SUBPIXEL_Y;
+    let parameters = RenderParameters {
+        width: options.width.div_ceil(SUBPIXEL_X),
+        height: options.height.div_ceil(SUBPIXEL_Y),
+        samples_per_pixel: options.samples_per_pixel * subpixels,
+        mode: RenderMode::FinalQuality,
+        depth: options.depth,
+    };
+    let mut renderer = Renderer::new(parameters, &scene.camera, world);
+    let format = ImageFormat::from_path(&options.output)?;
+
+    let mut stderr = std::io::stderr();
+    let interactive = stderr.is_terminal();
+    let mut reported_percent = None;
+    while !renderer.is_finished() {
+        renderer.render_step();
+
+        // without a terminal, only report every 10% to keep logs readable
+        let percent = (renderer.get_progress_percentage() * 100.0) as usize;
+        let report_step = if interactive { 1 } else { 10 };
+        if reported_percent.is_none_or(|reported| percent >= reported + report_step) {
+            reported_percent = Some(percent);
+            if interactive {
+                let _ = write!(stderr, "\rrendering {:3}%", percent);
+            } else {
+                let _ = writeln!(stderr, "rendering {:3}%", percent);
+            }
+        }
+    }
+    if interactive {
+        let _ = writeln!(stderr);
+    }
+    let _ = writeln!(
+        stderr,
+        "rendered {}x{} at {} spp in {:.2?}",
+        options.width,
+        options.height,
+        options.samples_per_pixel,
+        renderer.get_render_duration()
+    );
+
+    let mut image = renderer
+        .get_hdr_image()
+        .cropped(options.width, options.height);
+    // tone mapping is for displays, the float formats keep the full range
+    if !format.is_high_dynamic_range() {
+        let auto_exposure = auto_exposure(image.pixels());
+        image = image.map(|color| options.tone_mapping.apply(color, auto_exposure));
+    }
+    image
+        .save(&options.output)
+        .wrap_err_with(|| format!("failed to write {}", options.output.display()))
+}
+
+// renders the scene as terminal art. Without an output file, every line is printed to
+// stdout as soon as it is done, which works in any terminal or pipe, without taking
+// over the screen
+pub fn ansi(options: &AnsiOptions) -> Result<()> {
+    let scene = load_scene(options.scene.as_deref())?;
+    let world = build_world(&scene)?;
+    let parameters = RenderParameters {
+        width: options.width,
+        height: options.height,
+        samples_per_pixel: options.samples_per_pixel,
+        mode: RenderMode::FinalQuality,
+        depth: options.depth,
+    };
+    let mut renderer = Renderer::new(parameters, &scene.camera, world);
+    renderer.set_tone_mapping(options.tone_mapping);
+
+    let Some(output) = &options.output else {
+        let mut stdout = std::io::stdout().lock();
+        let mut printed_lines = 0;
+        while printed_lines < options.height {
+            renderer.render_step();
+            // auto exposure depends on the whole image
+            let completed_lines = if options.tone_mapping.auto_exposure && !renderer.is_finished() {
+                printed_lines
+            } else {
+                renderer.get_completed_lines()
+            };
+            let written = (printed_lines..completed_lines)
+                .try_for_each(|y| {
+                    writeln!(
+                        stdout,
+                        "{}",
+                        export::ansi_line(renderer.get_color_buffer(), y)
+                    )
+                })
+                .and_then(|_| stdout.flush());
+            match written {
+                // the reader went away, e.g. when piped into `head`
+                Err(error) if error.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+                result => result?,
+            }
+            printed_lines = completed_lines;
+        }
+        return Ok(());
+    };
+
+    while !renderer.is_finished() {
+        renderer.render_step();
+    }
+    let frame = renderer.get_color_buffer();
+    let is_html = output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("html"));
+    let text = if is_html {
+        export::to_html(frame)
+    } else {
+        export::to_ansi(frame)
+    };
+    std::fs::write(output, text).wrap_err_with(|| format!("failed to write {}", output.display()))
+}
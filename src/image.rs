@@ -0,0 +1,595 @@
+use crate::color::Color;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+// ppm and png are 8 bit and sRGB encoded, pfm stores the linear values as they are and
+// hdr (Radiance RGBE) stores them with a shared exponent per pixel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Ppm,
+    Png,
+    Pfm,
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("ppm") => Ok(ImageFormat::Ppm),
+            Some("png") => Ok(ImageFormat::Png),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("hdr") => Ok(ImageFormat::Hdr),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unsupported image format {}, expected .ppm, .png, .pfm or .hdr",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    // whether the format keeps values above one
+    pub fn is_high_dynamic_range(self) -> bool {
+        matches!(self, ImageFormat::Pfm | ImageFormat::Hdr)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// 16384 x 8192, the size of the largest common environment maps
+const MAX_PIXELS: usize = 1 << 27;
+
+// the pixel count of the size in a file header, so that a broken header fails before the
+// pixels are allocated
+fn pixel_count(width: usize, height: usize) -> io::Result<usize> {
+    match width.checked_mul(height) {
+        Some(count) if count <= MAX_PIXELS => Ok(count),
+        _ => Err(invalid_data("the image is too large")),
+    }
+}
+
+// the next whitespace separated word of a ppm header, skipping `#` comments
+fn read_ppm_token(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut in_comment = false;
+    loop {
+        let mut byte = [0];
+        if reader.read(&mut byte)? == 0 {
+            break;
+        }
+        let c = byte[0] as char;
+        match c {
+            '\n' if in_comment => in_comment = false,
+            _ if in_comment => {}
+            '#' => in_comment = true,
+            // a single whitespace character ends the header before binary data
+            _ if c.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    break;
+                }
+            }
+            _ => token.push(c),
+        }
+    }
+    if token.is_empty() {
+        return Err(invalid_data("unexpected end of the ppm file"));
+    }
+    Ok(token)
+}
+
+fn parse_ppm_number(token: &str) -> io::Result<usize> {
+    token.parse().map_err(|_| {
+        invalid_data(&format!(
+            "expected a number in the ppm file, found `{}`",
+            token
+        ))
+    })
+}
+
+// An image of linear, unclamped colors
+#[derive(Debug, Clone)]
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::black(); width * height],
+        }
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    // all pixels, row by row from the top
+    pub fn pixels(&self) -> impl Iterator<Item = Color> + '_ {
+        self.pixels.iter().copied()
+    }
+
+    // applies the function to every pixel, e.g. to tone map the image
+    pub fn map(&self, function: impl Fn(Color) -> Color) -> HdrImage {
+        HdrImage {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|&color| function(color)).collect(),
+        }
+    }
+
+    // the middle part of the image, which keeps the center where the camera looks
+    pub fn cropped(&self, width: usize, height: usize) -> HdrImage {
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+        let left = (self.width - width) / 2;
+        let top = (self.height - height) / 2;
+        let mut image = HdrImage::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                image.set_pixel(x, y, self.get_pixel(left + x, top + y));
+            }
+        }
+        image
+    }
+
+    // reads an image and converts it to linear colors. PPM and PNG files are 8 bit, PPM
+    // files can also use 16 bit values, PFM and HDR files keep their full range
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        let image = match ImageFormat::from_path(path)? {
+            ImageFormat::Ppm => Self::read_ppm(reader),
+            ImageFormat::Png => Self::read_png(reader),
+            ImageFormat::Pfm => Self::read_pfm(reader),
+            ImageFormat::Hdr => Self::read_hdr(reader),
+        }?;
+        // textures and environments look up at least one pixel
+        if image.width == 0 || image.height == 0 {
+            return Err(invalid_data("the image is empty"));
+        }
+        Ok(image)
+    }
+
+    // builds the image from gamma encoded channel values between 0 and max_value
+    fn from_encoded(
+        width: usize,
+        height: usize,
+        channels: usize,
+        max_value: f64,
+        values: impl Iterator<Item = u16>,
+    ) -> io::Result<Self> {
+        let values: Vec<f64> = values.map(|value| value as f64 / max_value).collect();
+        if values.len() < width * height * channels {
+            return Err(invalid_data("the image data is incomplete"));
+        }
+        let pixels = values
+            .chunks_exact(channels)
+            .take(width * height)
+            .map(|pixel| {
+                let encoded = match *pixel {
+                    [gray] | [gray, _] => Color::new(gray, gray, gray),
+                    [r, g, b, ..] => Color::new(r, g, b),
+                    _ => unreachable!("images have 1 to 4 channels"),
+                };
+                Color::from_srgb(encoded)
+            })
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // reads binary (P6) and plain text (P3) files
+    pub fn read_ppm(mut reader: impl BufRead) -> io::Result<Self> {
+        let magic = read_ppm_token(&mut reader)?;
+        let width = parse_ppm_number(&read_ppm_token(&mut reader)?)?;
+        let height = parse_ppm_number(&read_ppm_token(&mut reader)?)?;
+        let max_value = parse_ppm_number(&read_ppm_token(&mut reader)?)?;
+        if max_value == 0 || max_value > 65535 {
+            return Err(invalid_data(
+                "the maximum value of a ppm file must be 1 to 65535",
+            ));
+        }
+        let count = pixel_count(width, height)? * 3;
+        match magic.as_str() {
+            "P6" => {
+                let bytes_per_value = if max_value < 256 { 1 } else { 2 };
+                let mut data = vec![0; count * bytes_per_value];
+                reader.read_exact(&mut data)?;
+                // 16 bit values are big endian
+                let values: Vec<u16> = if bytes_per_value == 1 {
+                    data.iter().map(|&value| value as u16).collect()
+                } else {
+                    data.chunks_exact(2)
+                        .map(|value| u16::from_be_bytes([value[0], value[1]]))
+                        .collect()
+                };
+                Self::from_encoded(width, height, 3, max_value as f64, values.into_iter())
+            }
+            "P3" => {
+                let values = (0..count)
+                    .map(|_| parse_ppm_number(&read_ppm_token(&mut reader)?).map(|v| v as u16))
+                    .collect::<io::Result<Vec<u16>>>()?;
+                Self::from_encoded(width, height, 3, max_value as f64, values.into_iter())
+            }
+            _ => Err(invalid_data("not a ppm file, expected P3 or P6")),
+        }
+    }
+
+    pub fn read_png(reader: impl Read) -> io::Result<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        // palettes are expanded and 16 bit channels reduced, so every channel is one byte
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+        let channels = info.color_type.samples();
+        data.truncate(info.buffer_size());
+        Self::from_encoded(
+            info.width as usize,
+            info.height as usize,
+            channels,
+            255.0,
+            data.into_iter().map(u16::from),
+        )
+    }
+
+    // color (PF) and grayscale (Pf) files, the sign of the scale gives the byte order
+    pub fn read_pfm(mut reader: impl BufRead) -> io::Result<Self> {
+        let magic = read_ppm_token(&mut reader)?;
+        let channels = match magic.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid_data("not a pfm file, expected PF or Pf")),
+        };
+        let width = parse_ppm_number(&read_ppm_token(&mut reader)?)?;
+        let height = parse_ppm_number(&read_ppm_token(&mut reader)?)?;
+        let scale: f64 = read_ppm_token(&mut reader)?
+            .parse()
+            .map_err(|_| invalid_data("invalid scale in pfm file"))?;
+        let little_endian = scale < 0.0;
+
+        let mut data = vec![0; pixel_count(width, height)? * channels * 4];
+        reader.read_exact(&mut data)?;
+        let values: Vec<f64> = data
+            .chunks_exact(4)
+            .map(|bytes| {
+                let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+                if little_endian {
+                    f32::from_le_bytes(bytes) as f64
+                } else {
+                    f32::from_be_bytes(bytes) as f64
+                }
+            })
+            .collect();
+
+        let mut image = HdrImage::new(width, height);
+        for (index, pixel) in values.chunks_exact(channels).enumerate() {
+            let color = match *pixel {
+                [gray] => Color::new(gray, gray, gray),
+                [r, g, b] => Color::new(r, g, b),
+                _ => unreachable!("pfm files have 1 or 3 channels"),
+            };
+            // rows are stored from the bottom up
+            image.set_pixel(index % width, height - 1 - index / width, color);
+        }
+        Ok(image)
+    }
+
+    // Radiance RGBE files with top to bottom rows (-Y height +X width), which is what
+    // practically all files use. Scanlines can be flat or run length encoded.
+    pub fn read_hdr(mut reader: impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid_data("not a radiance hdr file"));
+        }
+        // the header ends with an empty line, followed by the resolution
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("the hdr header is incomplete"));
+            }
+            let setting = line.trim();
+            if setting.is_empty() {
+                break;
+            }
+            if let Some(format) = setting.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid_data("only rgbe hdr files are supported"));
+                }
+            }
+        }
+        line.clear();
+        reader.read_line(&mut line)?;
+        let resolution: Vec<&str> = line.split_whitespace().collect();
+        let (height, width) = match resolution[..] {
+            ["-Y", height, "+X", width] => (parse_ppm_number(height)?, parse_ppm_number(width)?),
+            _ => {
+                return Err(invalid_data(
+                    "unsupported hdr orientation, expected -Y h +X w",
+                ))
+            }
+        };
+        pixel_count(width, height)?;
+
+        let mut image = HdrImage::new(width, height);
+        let mut scanline = vec![[0u8; 4]; width];
+        for y in 0..height {
+            read_hdr_scanline(&mut reader, &mut scanline)?;
+            for (x, rgbe) in scanline.iter().enumerate() {
+                image.set_pixel(x, y, rgbe_to_color(*rgbe));
+            }
+        }
+        Ok(image)
+    }
+
+    // writes the image in the format given by the file extension, see ImageFormat
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let format = ImageFormat::from_path(path)?;
+        let file = BufWriter::new(std::fs::File::create(path)?);
+        match format {
+            ImageFormat::Ppm => self.write_ppm(file),
+            ImageFormat::Png => self.write_png(file),
+            ImageFormat::Pfm => self.write_pfm(file),
+            ImageFormat::Hdr => self.write_hdr(file),
+        }
+    }
+
+    // 8 bit sRGB values, row by row from the top
+    fn to_rgb8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|color| color.to_srgb8())
+            .collect()
+    }
+
+    pub fn write_ppm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.to_rgb8())?;
+        writer.flush()
+    }
+
+    pub fn write_png(&self, writer: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgb8())?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    // portable float map: 32 bit floats, little endian, rows from the bottom up
+    pub fn write_pfm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let color = self.get_pixel(x, y);
+                for channel in [color.x, color.y, color.z] {
+                    writer.write_all(&(channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+        writer.flush()
+    }
+
+    // radiance rgbe with flat scanlines
+    pub fn write_hdr(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+            self.height, self.width
+        )?;
+        for pixel in &self.pixels {
+            writer.write_all(&color_to_rgbe(*pixel))?;
+        }
+        writer.flush()
+    }
+}
+
+// a red, green and blue mantissa with a shared exponent, all channels below the mantissa
+// of the brightest one are stored with less precision
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::black();
+    }
+    let scale = 2f64.powi(e as i32 - 136);
+    Color::new(r as f64 * scale, g as f64 * scale, b as f64 * scale)
+}
+
+fn color_to_rgbe(color: Color) -> [u8; 4] {
+    let brightest = color.x.max(color.y).max(color.z);
+    if brightest < 1e-32 {
+        return [0; 4];
+    }
+    // brightest = mantissa * 2^exponent with mantissa in [0.5, 1)
+    let exponent = brightest.log2().floor() as i32 + 1;
+    let scale = 256.0 / 2f64.powi(exponent);
+    let channel = |value: f64| (value.max(0.0) * scale).min(255.0) as u8;
+    [
+        channel(color.x),
+        channel(color.y),
+        channel(color.z),
+        (exponent + 128).clamp(0, 255) as u8,
+    ]
+}
+
+// reads one scanline of rgbe pixels, which is either flat or, in the newer run length
+// encoding, stored one channel after the other
+fn read_hdr_scanline(reader: &mut impl BufRead, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut start = [0u8; 4];
+    reader.read_exact(&mut start)?;
+    let encoded = (8..0x8000).contains(&width)
+        && start[0] == 2
+        && start[1] == 2
+        && start[2] & 0x80 == 0
+        && ((start[2] as usize) << 8 | start[3] as usize) == width;
+    if !encoded {
+        scanline[0] = start;
+        for pixel in &mut scanline[1..] {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0u8; 1];
+            reader.read_exact(&mut count)?;
+            let (run, count) = if count[0] > 128 {
+                (true, count[0] as usize - 128)
+            } else {
+                (false, count[0] as usize)
+            };
+            if count == 0 || x + count > width {
+                return Err(invalid_data("invalid run length in hdr file"));
+            }
+            if run {
+                let mut value = [0u8; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                let mut values = vec![0u8; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+            }
+            x += count;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn written_images_read_back() {
+        let mut image = HdrImage::new(3, 2);
+        image.set_pixel(0, 0, Color::new(1.0, 0.25, 0.0));
+        image.set_pixel(2, 1, Color::new(0.5, 0.5, 1.0));
+
+        let mut ppm = Vec::new();
+        image.write_ppm(&mut ppm).unwrap();
+        let mut png = Vec::new();
+        image.write_png(&mut png).unwrap();
+
+        for decoded in [
+            HdrImage::read_ppm(ppm.as_slice()).unwrap(),
+            HdrImage::read_png(png.as_slice()).unwrap(),
+        ] {
+            assert_eq!((decoded.width, decoded.height), (3, 2));
+            for (x, y) in [(0, 0), (2, 1), (1, 1)] {
+                // 8 bit quantization loses a little precision
+                let difference = decoded.get_pixel(x, y) - image.get_pixel(x, y);
+                assert!(difference.length() < 0.01, "{:?}", difference);
+            }
+        }
+    }
+
+    #[test]
+    fn float_images_keep_their_range() {
+        let mut image = HdrImage::new(3, 2);
+        image.set_pixel(0, 0, Color::new(40.0, 0.25, 0.0));
+        image.set_pixel(2, 1, Color::new(0.5, 0.5, 1.0));
+
+        let mut pfm = Vec::new();
+        image.write_pfm(&mut pfm).unwrap();
+        let mut hdr = Vec::new();
+        image.write_hdr(&mut hdr).unwrap();
+
+        let pfm = HdrImage::read_pfm(pfm.as_slice()).unwrap();
+        let hdr = HdrImage::read_hdr(hdr.as_slice()).unwrap();
+        for (x, y) in [(0, 0), (2, 1), (1, 1)] {
+            let expected = image.get_pixel(x, y);
+            assert_eq!(pfm.get_pixel(x, y), expected);
+            // rgbe keeps about 8 bits relative to the brightest channel
+            let difference = hdr.get_pixel(x, y) - expected;
+            let brightest = expected.x.max(expected.y).max(expected.z);
+            assert!(difference.length() <= brightest / 100.0, "{:?}", difference);
+        }
+    }
+
+    #[test]
+    fn reads_run_length_encoded_hdr() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n".to_vec();
+        data.extend([2, 2, 0, 8]);
+        // red: a run of 8, green: 8 literal values, blue: two runs, exponent: a run
+        data.extend([128 + 8, 128]);
+        data.extend([8, 0, 16, 32, 48, 64, 80, 96, 112]);
+        data.extend([128 + 4, 0, 128 + 4, 255]);
+        data.extend([128 + 8, 129]);
+        let image = HdrImage::read_hdr(data.as_slice()).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(image.get_pixel(7, 0), Color::new(1.0, 0.875, 255.0 / 128.0));
+    }
+
+    #[test]
+    fn reads_plain_ppm_with_comments() {
+        let text = "P3\n# a comment\n2 1\n# another one\n255\n255 255 255\n0 0 0\n";
+        let image = HdrImage::read_ppm(text.as_bytes()).unwrap();
+        assert_eq!(image.get_pixel(0, 0), Color::white());
+        assert_eq!(image.get_pixel(1, 0), Color::black());
+    }
+
+    #[test]
+    fn cropping_keeps_the_middle() {
+        let mut image = HdrImage::new(4, 7);
+        image.set_pixel(1, 2, Color::white());
+        let cropped = image.cropped(2, 3);
+        assert_eq!((cropped.width, cropped.height), (2, 3));
+        assert_eq!(cropped.get_pixel(0, 0), Color::white());
+        assert_eq!(
+            cropped
+                .pixels()
+                .filter(|&pixel| pixel == Color::white())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn empty_images_are_not_loaded() {
+        let path = std::env::temp_dir().join(format!("rats-empty-{}.pfm", std::process::id()));
+        std::fs::write(&path, "PF\n0 5\n-1.0\n").unwrap();
+        let error = HdrImage::load(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn huge_sizes_in_headers_are_errors() {
+        let ppm = HdrImage::read_ppm("P6 100000 100000 255\n".as_bytes());
+        let pfm = HdrImage::read_pfm("PF\n100000 100000\n-1.0\n".as_bytes());
+        let hdr = HdrImage::read_hdr("#?RADIANCE\n\n-Y 100000 +X 100000\n".as_bytes());
+        for error in [ppm.err(), pfm.err(), hdr.err()] {
+            assert_eq!(error.unwrap().kind(), io::ErrorKind::InvalidData);
+        }
+        let overflowing = format!("P3 {} 2 255\n", usize::MAX);
+        assert!(HdrImage::read_ppm(overflowing.as_bytes()).is_err());
+    }
+}
@@ -10,11 +10,15 @@
 use std::{
     io::{self, stdout, Stdout},
     panic::{set_hook, take_hook},
+    path::PathBuf,
     time::{Duration, Instant},
 };
 
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, MouseButton, MouseEvent,
+        MouseEventKind,
+    },
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
@@ -29,9 +33,24 @@ use ratatui::{
 };
 
 fn main() -> Result<()> {
+    color_eyre::install()?;
+    let command = cli::parse_args(std::env::args().skip(1))
+        .map_err(|message| eyre!("{}\n\n{}", message, cli::USAGE))?;
+    let scene = match command {
+        Command::Interactive { scene } => headless::load_scene(scene.as_deref())?,
+        Command::Render(options) => return headless::render(&options),
+        Command::Ansi(options) => return headless::ansi(&options),
+        Command::Help => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+    };
+
+    let world = headless::build_world(&scene)?;
+
     init_panic_hook();
     let terminal = &mut init_tui()?;
-    App::new().run(terminal)?;
+    App::new(scene, world).run(terminal)?;
     restore_tui()?;
     Ok(())
 }
@@ -45,29 +64,70 @@ pub fn init_panic_hook() {
     }));
 }
 
+mod aabb;
+mod background;
 mod buffer_display;
+mod bvh;
 mod camera;
+mod cli;
 mod color;
+mod environment;
+mod export;
 mod geometry;
+mod headless;
+mod image;
 mod materials;
 mod maths;
+mod medium;
+mod mesh;
+mod noise;
+mod pdf;
 mod random;
+mod render_service;
 mod renderer;
 mod scene;
+mod scene_format;
+mod sky;
 mod terminal;
+mod texture;
+mod tonemap;
+mod transform;
+use background::Background;
 use buffer_display::{ImageDisplay, ImageDisplayState};
-use color_eyre::Result;
-use renderer::Renderer;
+use camera::CameraSettings;
+use cli::Command;
+use color_eyre::{eyre::eyre, Result};
+use render_service::{RenderService, RenderSnapshot};
+use renderer::{FrameBuffer, RenderMode, RenderParameters};
+use scene::{Scene, World};
+use std::sync::Arc;
+use tonemap::ToneMapping;
 
 struct App {
     tick_count: u64,
     marker: Marker,
-    renderer: Renderer,
+    render_service: RenderService,
+    world: Arc<World>, // shared with the render thread
+    render_parameters: RenderParameters,
+    snapshot: RenderSnapshot, // the most recent state received from the render thread
+    camera: CameraSettings,
+    scene_camera: CameraSettings, // the camera as defined in the scene, to reset to
+    autofocus_missed: bool,       // set when autofocus found nothing to focus on
+    background: Background,       // changes when the sun of a sky is moved
+    scene_directory: PathBuf,     // that the paths of the scene are relative to
+    tone_mapping: ToneMapping,
+    last_camera_move: Option<Instant>, // set while the render is a low-sample preview
+    last_drag_position: Option<(u16, u16)>,
     display_state: ImageDisplayState,
     show_side_panel: bool,
 }
 
-fn title_block(title: &str) -> Block {
+// while the camera moves, only a few samples are taken so that the preview stays interactive
+const PREVIEW_SAMPLES_PER_PIXEL: usize = terminal::SUBPIXEL_X * terminal::SUBPIXEL_Y;
+// the full quality render starts once the camera has been still for this long
+const PREVIEW_DURATION: Duration = Duration::from_millis(300);
+
+fn title_block(title: &str) -> Block<'_> {
     let title = Title::from(title).alignment(Alignment::Center);
     Block::new()
         .borders(Borders::NONE)
@@ -77,11 +137,37 @@ fn title_block(title: &str) -> Block {
 }
 
 impl App {
-    fn new() -> Self {
+    fn new(scene: Scene, world: Arc<World>) -> Self {
+        let render_parameters = RenderParameters::default();
+        let camera = scene.camera;
+        let render_service = RenderService::new(render_parameters, camera, Arc::clone(&world));
+        render_service.restart(render_parameters);
         Self {
             tick_count: 0,
             marker: Marker::Dot,
-            renderer: Renderer::new(192, 72),
+            render_service,
+            world,
+            render_parameters,
+            snapshot: RenderSnapshot {
+                frame: FrameBuffer::new(render_parameters.width, render_parameters.height),
+                parameters: render_parameters,
+                progress: 0.0,
+                samples_taken: 0,
+                render_duration: Duration::ZERO,
+                object_count: 0,
+                bvh_stats: Default::default(),
+                tone_mapping: ToneMapping::default(),
+                exposure: 0.0,
+                is_running: true,
+            },
+            camera,
+            scene_camera: camera,
+            autofocus_missed: false,
+            background: scene.background,
+            scene_directory: scene.directory,
+            tone_mapping: ToneMapping::default(),
+            last_camera_move: None,
+            last_drag_position: None,
             display_state: ImageDisplayState {
                 x: 0.0,
                 y: 0.0,
@@ -98,23 +184,15 @@ impl App {
             let _ = terminal.draw(|frame| self.ui(frame));
             let timeout = tick_rate.saturating_sub(last_tick.elapsed());
             if event::poll(timeout)? {
-                if let Event::Key(key) = event::read()? {
-                    match key.code {
-                        KeyCode::Char('q') => break,
-                        KeyCode::Char(' ') => self.show_side_panel = !self.show_side_panel,
-                        KeyCode::Char('j') => self.display_state.zoom += 0.1,
-                        KeyCode::Char('k') => self.display_state.zoom -= 0.1,
-                        KeyCode::Char('r') => {
-                            self.display_state.zoom = 1.0;
-                            self.display_state.x = 0.0;
-                            self.display_state.y = 0.0;
+                match event::read()? {
+                    Event::Key(key) => {
+                        if key.code == KeyCode::Char('q') {
+                            break;
                         }
-                        KeyCode::Right => self.display_state.x += 1.0,
-                        KeyCode::Left => self.display_state.x -= 1.0,
-                        KeyCode::Up => self.display_state.y -= 1.0,
-                        KeyCode::Down => self.display_state.y += 1.0,
-                        _ => {}
+                        self.on_key(key.code);
                     }
+                    Event::Mouse(mouse) => self.on_mouse(mouse),
+                    _ => {}
                 }
             }
 
@@ -126,17 +204,188 @@ impl App {
         Ok(())
     }
 
+    fn on_key(&mut self, key: KeyCode) {
+        // orbiting and strafing scale with the distance to the look_at point
+        let distance = (self.camera.look_from - self.camera.look_at).length();
+        match key {
+            KeyCode::Char(' ') => self.show_side_panel = !self.show_side_panel,
+            // 3D camera
+            KeyCode::Left => self.move_camera(|camera| camera.orbit(-5.0, 0.0)),
+            KeyCode::Right => self.move_camera(|camera| camera.orbit(5.0, 0.0)),
+            KeyCode::Up => self.move_camera(|camera| camera.orbit(0.0, 5.0)),
+            KeyCode::Down => self.move_camera(|camera| camera.orbit(0.0, -5.0)),
+            KeyCode::Char('w') => self.move_camera(|camera| camera.dolly(distance * 0.1)),
+            KeyCode::Char('s') => self.move_camera(|camera| camera.dolly(-distance * 0.1)),
+            KeyCode::Char('a') => self.move_camera(|camera| camera.strafe(-distance * 0.05, 0.0)),
+            KeyCode::Char('d') => self.move_camera(|camera| camera.strafe(distance * 0.05, 0.0)),
+            KeyCode::Char('r') => self.move_camera(|camera| camera.strafe(0.0, distance * 0.05)),
+            KeyCode::Char('f') => self.move_camera(|camera| camera.strafe(0.0, -distance * 0.05)),
+            KeyCode::Char('c') => {
+                let scene_camera = self.scene_camera;
+                self.move_camera(|camera| *camera = scene_camera);
+            }
+            // depth of field
+            KeyCode::Char('F') => {
+                let mut camera = self.camera;
+                if camera.autofocus(self.world.objects.as_ref()) {
+                    self.move_camera(|moved| *moved = camera);
+                } else {
+                    // the focus stays as it is, so the render goes on
+                    self.autofocus_missed = true;
+                }
+            }
+            KeyCode::Char('[') => self.move_camera(|camera| {
+                camera.defocus_angle = (camera.defocus_angle - 0.5).max(0.0);
+            }),
+            KeyCode::Char(']') => self.move_camera(|camera| camera.defocus_angle += 0.5),
+            // time of day, if the scene has a sky
+            KeyCode::Char(',') => self.move_sun(-5.0, 0.0),
+            KeyCode::Char('.') => self.move_sun(5.0, 0.0),
+            KeyCode::Char('<') => self.move_sun(0.0, -15.0),
+            KeyCode::Char('>') => self.move_sun(0.0, 15.0),
+            // 2D view of the rendered image
+            KeyCode::Char('i') => self.display_state.zoom += 0.1,
+            KeyCode::Char('o') => self.display_state.zoom -= 0.1,
+            KeyCode::Char('h') => self.display_state.x -= 1.0,
+            KeyCode::Char('l') => self.display_state.x += 1.0,
+            KeyCode::Char('k') => self.display_state.y -= 1.0,
+            KeyCode::Char('j') => self.display_state.y += 1.0,
+            KeyCode::Char('0') => {
+                self.display_state.zoom = 1.0;
+                self.display_state.x = 0.0;
+                self.display_state.y = 0.0;
+            }
+            // exposure and tone mapping of the display
+            KeyCode::Char('e') => self.change_tone_mapping(|tone| tone.exposure -= 0.5),
+            KeyCode::Char('E') => self.change_tone_mapping(|tone| tone.exposure += 0.5),
+            KeyCode::Char('t') => {
+                self.change_tone_mapping(|tone| tone.operator = tone.operator.next())
+            }
+            KeyCode::Char('A') => {
+                self.change_tone_mapping(|tone| tone.auto_exposure = !tone.auto_exposure)
+            }
+            // rendering
+            KeyCode::Char('x') => {
+                // a pending preview would otherwise restart the render right away
+                self.last_camera_move = None;
+                self.render_service.cancel();
+            }
+            KeyCode::Char('g') => self.render_service.start(),
+            KeyCode::Char('+') => self.change_samples_per_pixel(2.0),
+            KeyCode::Char('-') => self.change_samples_per_pixel(0.5),
+            KeyCode::Char('m') => self.toggle_render_mode(),
+            _ => {}
+        }
+    }
+
+    // dragging with the left mouse button orbits the camera
+    fn on_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                self.last_drag_position = Some((mouse.column, mouse.row));
+            }
+            MouseEventKind::Drag(MouseButton::Left) => {
+                if let Some((column, row)) = self.last_drag_position {
+                    // terminal cells are about twice as high as wide
+                    let yaw = (mouse.column as f64 - column as f64) * 2.0;
+                    let pitch = (mouse.row as f64 - row as f64) * 4.0;
+                    self.move_camera(|camera| camera.orbit(-yaw, pitch));
+                }
+                self.last_drag_position = Some((mouse.column, mouse.row));
+            }
+            MouseEventKind::Up(MouseButton::Left) => self.last_drag_position = None,
+            _ => {}
+        }
+    }
+
     fn on_tick(&mut self) {
         self.tick_count += 1;
         self.marker = Marker::HalfBlock;
 
-        self.renderer.render_step();
+        // the camera stopped moving, replace the preview with the full render
+        if let Some(last_camera_move) = self.last_camera_move {
+            if last_camera_move.elapsed() >= PREVIEW_DURATION {
+                self.last_camera_move = None;
+                self.render_service.restart(self.render_parameters);
+            }
+        }
+
+        if let Some(snapshot) = self.render_service.latest_snapshot() {
+            self.snapshot = snapshot;
+        }
+    }
+
+    // applies the movement to the camera and restarts the render as a cheap preview
+    fn move_camera(&mut self, movement: impl FnOnce(&mut CameraSettings)) {
+        movement(&mut self.camera);
+        self.autofocus_missed = false;
+        self.render_service
+            .move_camera(self.camera, self.preview_parameters());
+        self.last_camera_move = Some(Instant::now());
+    }
+
+    // turns the sun of the sky by degrees and previews the scene under the new sky
+    fn move_sun(&mut self, elevation_change: f64, azimuth_change: f64) {
+        let Background::Sky {
+            elevation, azimuth, ..
+        } = &mut self.background
+        else {
+            return;
+        };
+        *elevation = (*elevation + elevation_change).clamp(0.0, 90.0);
+        *azimuth = (*azimuth + azimuth_change).rem_euclid(360.0);
+        // building a sky can not fail, only environment images are loaded
+        let Ok(background) = self
+            .background
+            .build(self.world.working_space, &self.scene_directory)
+        else {
+            return;
+        };
+        self.world = Arc::new(self.world.with_background(background));
+        self.render_service
+            .set_world(Arc::clone(&self.world), self.preview_parameters());
+        self.last_camera_move = Some(Instant::now());
+    }
+
+    // while something moves, only a few samples are taken
+    fn preview_parameters(&self) -> RenderParameters {
+        RenderParameters {
+            samples_per_pixel: PREVIEW_SAMPLES_PER_PIXEL,
+            mode: RenderMode::Progressive {
+                samples_per_pass: PREVIEW_SAMPLES_PER_PIXEL,
+            },
+            ..self.render_parameters
+        }
+    }
+
+    // the rendered samples are kept, only the display changes
+    fn change_tone_mapping(&mut self, change: impl FnOnce(&mut ToneMapping)) {
+        change(&mut self.tone_mapping);
+        self.render_service.set_tone_mapping(self.tone_mapping);
+    }
+
+    // scales the sample count and restarts the render with it
+    fn change_samples_per_pixel(&mut self, factor: f64) {
+        let samples_per_pixel = (self.render_parameters.samples_per_pixel as f64 * factor) as usize;
+        // the renderer takes the samples in multiples of the subpixel count
+        self.render_parameters.samples_per_pixel =
+            samples_per_pixel.clamp(terminal::SUBPIXEL_X * terminal::SUBPIXEL_Y, 1 << 16);
+        self.render_service.restart(self.render_parameters);
+    }
+
+    // switches between progressive and final quality rendering and restarts the render
+    fn toggle_render_mode(&mut self) {
+        self.render_parameters.mode = match self.render_parameters.mode {
+            RenderMode::Progressive { .. } => RenderMode::FinalQuality,
+            RenderMode::FinalQuality => RenderParameters::default().mode,
+        };
+        self.render_service.restart(self.render_parameters);
     }
 
     fn ui(&mut self, frame: &mut Frame) {
         if !self.show_side_panel {
             frame.render_stateful_widget(
-                ImageDisplay::new(self.renderer.get_color_buffer()),
+                ImageDisplay::new(&self.snapshot.frame),
                 frame.size(),
                 &mut self.display_state,
             );
@@ -149,7 +398,7 @@ impl App {
 
         self.render_side_panel(menu_area, frame.buffer_mut());
         frame.render_stateful_widget(
-            ImageDisplay::new(self.renderer.get_color_buffer()),
+            ImageDisplay::new(&self.snapshot.frame),
             render_area,
             &mut self.display_state,
         );
@@ -159,11 +408,46 @@ impl App {
         // Calculate and display the rendering resolution
         let resolution = format!(
             "{}x{}",
-            self.renderer.get_color_buffer().width,
-            self.renderer.get_color_buffer().height
+            self.snapshot.frame.width, self.snapshot.frame.height
+        );
+        let samples_per_pixel = format!(
+            "{}/{}",
+            self.snapshot.samples_taken, self.snapshot.parameters.samples_per_pixel
         );
-        let objects_count = format!("{}", self.renderer.get_scene_object_count());
-        let render_duration = format!("{:.2?}", self.renderer.get_render_duration());
+        let mode = match self.snapshot.parameters.mode {
+            RenderMode::Progressive { .. } => "Progressive",
+            RenderMode::FinalQuality => "Final Quality",
+        };
+        let objects_count = format!("{}", self.snapshot.object_count);
+        let bvh_build_duration = format!("{:.2?}", self.snapshot.bvh_stats.build_duration);
+        let bvh_depth = format!("{}", self.snapshot.bvh_stats.depth);
+        let render_duration = format!("{:.2?}", self.snapshot.render_duration);
+        let mut focus = format!(
+            "{:.2} @ {:.1}°",
+            self.camera.focus_distance, self.camera.defocus_angle
+        );
+        if self.autofocus_missed {
+            focus.push_str(", no hit");
+        }
+        let tone_mapping = self.snapshot.tone_mapping;
+        let exposure = if tone_mapping.auto_exposure {
+            format!("auto {:+.1}", self.snapshot.exposure)
+        } else {
+            format!("{:+.1} EV", self.snapshot.exposure)
+        };
+        let sun = match self.background {
+            Background::Sky {
+                elevation, azimuth, ..
+            } => format!("{:.0}° @ {:.0}°", elevation, azimuth),
+            _ => "-".to_string(),
+        };
+        let status = if self.snapshot.is_running {
+            "Rendering"
+        } else if self.snapshot.progress >= 1.0 {
+            "Done"
+        } else {
+            "Cancelled"
+        };
 
         // Create a table widget
         let widths = [
@@ -172,8 +456,17 @@ impl App {
         ];
         let rows = [
             Row::new(vec!["Resolution", &resolution]),
+            Row::new(vec!["Mode", mode]),
+            Row::new(vec!["Samples/Pixel", &samples_per_pixel]),
             Row::new(vec!["Objects", &objects_count]),
+            Row::new(vec!["BVH Build", &bvh_build_duration]),
+            Row::new(vec!["BVH Depth", &bvh_depth]),
+            Row::new(vec!["Focus", &focus]),
+            Row::new(vec!["Sun", &sun]),
+            Row::new(vec!["Exposure", &exposure]),
+            Row::new(vec!["Tone Map", tone_mapping.operator.name()]),
             Row::new(vec!["Render Duration", &render_duration]),
+            Row::new(vec!["Status", status]),
         ];
         Widget::render(
             Table::new(rows, widths).header(Row::new(vec!["Metric", "Value"])),
@@ -182,7 +475,7 @@ impl App {
         );
 
         // Calculate and display the current progress gauge
-        let progress = self.renderer.get_progress_percentage();
+        let progress = self.snapshot.progress;
         let label = format!("{:.1}%", progress * 100.0);
 
         //let title = "Progress".to_string();
@@ -202,11 +495,13 @@ impl App {
 fn init_tui() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
     enable_raw_mode()?;
     stdout().execute(EnterAlternateScreen)?;
+    stdout().execute(EnableMouseCapture)?;
     Terminal::new(CrosstermBackend::new(stdout()))
 }
 
 fn restore_tui() -> io::Result<()> {
     disable_raw_mode()?;
+    stdout().execute(DisableMouseCapture)?;
     stdout().execute(LeaveAlternateScreen)?;
     Ok(())
 }
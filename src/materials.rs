@@ -1,47 +1,66 @@
 use crate::color::Color;
 use crate::geometry::{HitRecord, Ray};
+use crate::pdf::{CosinePdf, Pdf, SpherePdf};
+use crate::texture::{SolidColor, Texture};
+use std::sync::Arc;
 
 pub struct ScatterRecord {
     pub attenuation: Color,
-    pub scattered_ray: Ray,
+    pub scatter: Scatter,
 }
 
-pub trait Material {
+pub enum Scatter {
+    // a single direction, like a mirror reflection, which light sampling cannot find
+    Specular(Ray),
+    // directions from the pdf, which is also the share of the light that each direction
+    // scatters, so a sample is weighted with the attenuation only
+    Diffuse(Box<dyn Pdf>),
+}
+
+// materials are shared between the render threads, so they need to be Send + Sync
+pub trait Material: Send + Sync {
     fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord>;
+
+    // the light given off at the hit point, only light sources emit anything
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        Color::black()
+    }
 }
 
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
         Self { albedo }
     }
 }
 
 impl Material for Lambertian {
     fn scatter(&self, _ray: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
-        let mut scatter_direction = hit_record.normal + crate::random::random_vec3_unit();
-
-        if scatter_direction.near_zero() {
-            scatter_direction = hit_record.normal;
-        }
-
         Some(ScatterRecord {
-            attenuation: self.albedo,
-            scattered_ray: Ray::new(hit_record.point, scatter_direction),
+            attenuation: self.albedo.value(hit_record.uv, hit_record.point),
+            scatter: Scatter::Diffuse(Box::new(CosinePdf::new(hit_record.normal))),
         })
     }
 }
 
 pub struct Metal {
-    albedo: Color,
+    albedo: Arc<dyn Texture>,
     fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, fuzz: f64) -> Self {
         Self { albedo, fuzz }
     }
 }
@@ -52,8 +71,12 @@ impl Material for Metal {
         let scattered_direction = reflected + crate::random::random_vec3_unit() * self.fuzz;
 
         Some(ScatterRecord {
-            attenuation: self.albedo,
-            scattered_ray: Ray::new(hit_record.point, scattered_direction),
+            attenuation: self.albedo.value(hit_record.uv, hit_record.point),
+            scatter: Scatter::Specular(Ray::with_time(
+                hit_record.point,
+                scattered_direction,
+                ray.time,
+            )),
         })
     }
 }
@@ -97,7 +120,54 @@ impl Material for Dielectric {
 
         Some(ScatterRecord {
             attenuation,
-            scattered_ray: Ray::new(hit_record.point, direction),
+            scatter: Scatter::Specular(Ray::with_time(hit_record.point, direction, ray.time)),
         })
     }
 }
+
+// The phase function of a participating medium like smoke or fog: light is scattered
+// into a uniformly random direction, regardless of where it came from
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
+
+impl Isotropic {
+    pub fn new(albedo: Color) -> Self {
+        Self::textured(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Self {
+        Self { albedo }
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, _ray: &Ray, hit_record: &HitRecord) -> Option<ScatterRecord> {
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(hit_record.uv, hit_record.point),
+            scatter: Scatter::Diffuse(Box::new(SpherePdf)),
+        })
+    }
+}
+
+// A light source that emits the same light in all directions from both sides of its
+// surface, and does not reflect anything
+pub struct DiffuseLight {
+    emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self { emit }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _hit_record: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _hit_record: &HitRecord) -> Color {
+        self.emit
+    }
+}
@@ -13,7 +13,7 @@ pub struct Vec3 {
 
 impl Vec2 {
     // construct a new Vec2
-    pub fn new(x: f64, y: f64) -> Self {
+    pub const fn new(x: f64, y: f64) -> Self {
         Self { x, y }
     }
     pub fn zero() -> Self {
@@ -106,7 +106,7 @@ impl std::ops::Div<f64> for Vec2 {
 
 impl Vec3 {
     // construct a new Vec3
-    pub fn new(x: f64, y: f64, z: f64) -> Self {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
         Self { x, y, z }
     }
     pub fn zero() -> Self {
@@ -158,6 +158,25 @@ impl Vec3 {
     pub fn reflect(&self, n: Vec3) -> Vec3 {
         *self - n * 2.0 * self.dot(n)
     }
+
+    // rotates the vector around the (normalized) axis by the angle in radians,
+    // using Rodrigues' rotation formula
+    pub fn rotated(&self, axis: Vec3, angle: f64) -> Vec3 {
+        let (sin, cos) = angle.sin_cos();
+        *self * cos + axis.cross(*self) * sin + axis * axis.dot(*self) * (1.0 - cos)
+    }
+
+    // two unit vectors that form an orthonormal basis with this (normalized) vector
+    pub fn perpendicular_basis(&self) -> (Vec3, Vec3) {
+        // any vector that is not parallel works as a starting point
+        let helper = if self.x.abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let tangent = helper.cross(*self).normalized();
+        (tangent, self.cross(tangent))
+    }
 }
 
 // implement the Add trait
@@ -249,6 +268,253 @@ pub fn refract(uv: Vec3, n: Vec3, etai_over_etat: f64) -> Vec3 {
 
 pub type Point = Vec3;
 
+// A unit quaternion w + xi + yj + zk, which describes a rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quat {
+    pub fn identity() -> Self {
+        Self {
+            w: 1.0,
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        }
+    }
+
+    // rotation around the axis by the angle in radians, counterclockwise when looking
+    // against the axis
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Self {
+        let axis = axis.normalized();
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Self {
+            w: cos,
+            x: axis.x * sin,
+            y: axis.y * sin,
+            z: axis.z * sin,
+        }
+    }
+
+    pub fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn normalized(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat {
+            w: self.w / length,
+            x: self.x / length,
+            y: self.y / length,
+            z: self.z / length,
+        }
+    }
+
+    // the inverse rotation
+    pub fn conjugate(&self) -> Quat {
+        Quat {
+            w: self.w,
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        // v + 2 q x (q x v + w v), with q the vector part
+        let q = Vec3::new(self.x, self.y, self.z);
+        let t = q.cross(v) * 2.0;
+        v + t * self.w + q.cross(t)
+    }
+
+    // spherical interpolation along the shorter arc between two rotations
+    pub fn slerp(a: Quat, b: Quat, t: f64) -> Quat {
+        let mut cos = a.dot(&b);
+        let mut b = b;
+        if cos < 0.0 {
+            cos = -cos;
+            b = Quat {
+                w: -b.w,
+                x: -b.x,
+                y: -b.y,
+                z: -b.z,
+            };
+        }
+        // nearly equal rotations, fall back to linear interpolation
+        let (wa, wb) = if cos > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let angle = cos.acos();
+            let sin = angle.sin();
+            (((1.0 - t) * angle).sin() / sin, (t * angle).sin() / sin)
+        };
+        Quat {
+            w: wa * a.w + wb * b.w,
+            x: wa * a.x + wb * b.x,
+            y: wa * a.y + wb * b.y,
+            z: wa * a.z + wb * b.z,
+        }
+        .normalized()
+    }
+}
+
+// applies the right rotation first, then the left one
+impl std::ops::Mul for Quat {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        Self {
+            w: self.w * other.w - self.x * other.x - self.y * other.y - self.z * other.z,
+            x: self.w * other.x + self.x * other.w + self.y * other.z - self.z * other.y,
+            y: self.w * other.y - self.x * other.z + self.y * other.w + self.z * other.x,
+            z: self.w * other.z + self.x * other.y - self.y * other.x + self.z * other.w,
+        }
+    }
+}
+
+// A 4x4 matrix for affine transformations of points and vectors, stored row by row.
+// Points are column vectors, so in `a * b` the transformation b is applied first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        Self { m }
+    }
+
+    pub fn translation(offset: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scaling(factors: Vec3) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        matrix
+    }
+
+    pub fn rotation(rotation: Quat) -> Self {
+        let Quat { w, x, y, z } = rotation.normalized();
+        Self {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    // scales first, then rotates and then translates
+    pub fn from_scale_rotation_translation(scale: Vec3, rotation: Quat, translation: Vec3) -> Self {
+        Self::translation(translation) * Self::rotation(rotation) * Self::scaling(scale)
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Mat4 { m }
+    }
+
+    // Gauss-Jordan elimination with partial pivoting, None if the matrix is singular
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inverse = Mat4::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap_or(column);
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for j in 0..4 {
+                        a[row][j] -= factor * a[column][j];
+                        inverse[row][j] -= factor * inverse[column][j];
+                    }
+                }
+            }
+        }
+        Some(Mat4 { m: inverse })
+    }
+
+    pub fn transform_point(&self, p: Point) -> Point {
+        let m = &self.m;
+        Point::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    // directions are not affected by the translation
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl std::ops::Mul for Mat4 {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
@@ -285,6 +551,15 @@ impl Interval {
     pub fn clamp(&self, value: f64) -> f64 {
         value.max(self.min).min(self.max)
     }
+
+    // the smallest interval that contains both intervals
+    pub fn union(&self, other: &Interval) -> Interval {
+        Interval::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn center(&self) -> f64 {
+        (self.min + self.max) / 2.0
+    }
 }
 
 impl Default for Interval {
@@ -409,4 +684,38 @@ mod tests {
         let vec = Vec3::zero();
         assert_eq!(vec, Vec3::new(0.0, 0.0, 0.0));
     }
+
+    #[test]
+    fn quaternion_rotation_matches_rodrigues() {
+        let axis = Vec3::new(1.0, 2.0, -0.5).normalized();
+        let v = Vec3::new(0.3, -1.0, 2.0);
+        let angle = 1.2;
+        let quat = Quat::from_axis_angle(axis, angle);
+        let expected = v.rotated(axis, angle);
+        assert!((quat.rotate(v) - expected).length() < 1e-12);
+        assert!((Mat4::rotation(quat).transform_vector(v) - expected).length() < 1e-12);
+        // half of the way is half of the angle
+        let half = Quat::slerp(Quat::identity(), quat, 0.5);
+        assert!((half.rotate(v) - v.rotated(axis, angle / 2.0)).length() < 1e-12);
+    }
+
+    #[test]
+    fn matrix_inverse_undoes_the_transform() {
+        let matrix = Mat4::from_scale_rotation_translation(
+            Vec3::new(2.0, 0.5, 1.0),
+            Quat::from_axis_angle(Vec3::new(0.0, 1.0, 1.0), 0.7),
+            Vec3::new(1.0, -2.0, 3.0),
+        );
+        let inverse = matrix.inverse().unwrap();
+        let p = Point::new(0.4, 5.0, -1.0);
+        assert!((inverse.transform_point(matrix.transform_point(p)) - p).length() < 1e-12);
+        let product = matrix * inverse;
+        for (i, row) in product.m.iter().enumerate() {
+            for (j, value) in row.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-12);
+            }
+        }
+        assert!(Mat4::scaling(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+    }
 }
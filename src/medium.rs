@@ -0,0 +1,131 @@
+//! Participating media, which scatter light inside their volume instead of at a surface.
+
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::geometry::*;
+use crate::materials::Material;
+use crate::maths::*;
+use crate::random::random_double;
+use std::sync::Arc;
+
+// distance a ray travels through a medium of the density before it hits a particle,
+// exponentially distributed so that the chance to scatter is the same everywhere
+fn scatter_distance(density: f64) -> f64 {
+    -random_double().ln() / density
+}
+
+// A volume of constant density, like smoke, inside a closed boundary object. Rays that
+// enter it scatter at a random distance inside, or pass through if they leave it first.
+// The phase function is the material of the scattered hits, usually `Isotropic`.
+pub struct ConstantMedium<H: Hittable + ?Sized> {
+    boundary: Arc<H>,
+    density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl<H: Hittable + ?Sized> ConstantMedium<H> {
+    pub fn new(boundary: Arc<H>, density: f64, phase_function: Arc<dyn Material>) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+}
+
+impl<H: Hittable + ?Sized> Hittable for ConstantMedium<H> {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        // where the ray line enters and leaves the boundary, also if the ray starts inside
+        let enter = self.boundary.hit(ray, &Interval::universe())?;
+        let exit = self
+            .boundary
+            .hit(ray, &Interval::new(enter.t + 0.0001, f64::INFINITY))?;
+
+        let enter_t = enter.t.max(interval.min);
+        let exit_t = exit.t.min(interval.max);
+        if enter_t >= exit_t {
+            return None;
+        }
+        let enter_t = enter_t.max(0.0);
+
+        let ray_length = ray.direction.length();
+        let distance_inside = (exit_t - enter_t) * ray_length;
+        let distance = scatter_distance(self.density);
+        if distance > distance_inside {
+            return None;
+        }
+
+        let t = enter_t + distance / ray_length;
+        // normal and side do not mean anything inside a volume
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: Vec3::new(1.0, 0.0, 0.0),
+            t,
+            uv: Vec2::new(0.0, 0.0),
+            front_face: true,
+            material: Arc::clone(&self.phase_function),
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.boundary.bounding_box()
+    }
+}
+
+// Homogeneous fog that fills the whole scene. Light from a surface fades into the fog color
+// with its distance from the viewer, so that far away objects are hazier than close ones.
+// Rays that leave the scene see the background as it is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fog {
+    pub density: f64,
+    pub color: Color, // the light the fog scatters towards the viewer
+}
+
+impl Fog {
+    // the share of the light from the distance that makes it through the fog
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        (-self.density * distance).exp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::{Isotropic, Lambertian};
+
+    #[test]
+    fn denser_media_scatter_closer_to_their_boundary() {
+        let boundary = Arc::new(Cuboid::new(
+            Point::new(-1.0, -1.0, -11.0),
+            Point::new(1.0, 1.0, -1.0),
+            Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5))),
+        ));
+        let smoke: Arc<dyn Material> = Arc::new(Isotropic::new(Vec3::new(0.5, 0.5, 0.5)));
+        let ray = Ray::new(Point::zero(), Vec3::new(0.0, 0.0, -2.0));
+        let interval = Interval::new(0.001, f64::INFINITY);
+
+        let mean_depth = |density: f64| {
+            let medium = ConstantMedium::new(Arc::clone(&boundary), density, Arc::clone(&smoke));
+            let samples = 10_000;
+            let (mut hits, mut depth) = (0, 0.0);
+            for _ in 0..samples {
+                if let Some(record) = medium.hit(&ray, &interval) {
+                    // the hit is inside the box, and t is along the unnormalized ray
+                    assert!(record.point.z <= -1.0 && record.point.z >= -11.0);
+                    assert!((record.point.z + 2.0 * record.t).abs() < 1e-9);
+                    hits += 1;
+                    depth += -1.0 - record.point.z;
+                }
+            }
+            (hits as f64 / samples as f64, depth / hits as f64)
+        };
+
+        // the chance to pass the 10 units is exp(-density * 10)
+        let (thin_hits, thin_depth) = mean_depth(0.05);
+        assert!((thin_hits - (1.0 - (-0.5_f64).exp())).abs() < 0.03);
+        let (dense_hits, dense_depth) = mean_depth(2.0);
+        assert!(dense_hits > 0.99);
+        assert!((dense_depth - 0.5).abs() < 0.05);
+        assert!(thin_depth > dense_depth);
+    }
+}
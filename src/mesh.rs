@@ -0,0 +1,528 @@
+//! Triangle meshes loaded from Wavefront OBJ files.
+//!
+//! Only the parts of the format that describe geometry are read: vertices (`v`), texture
+//! coordinates (`vt`), normals (`vn`) and polygonal faces (`f`), which are split into
+//! triangles. `g` and `o` start named groups, and `usemtl` assigns a material to the
+//! faces that follow it. Everything else, e.g. smoothing groups, is ignored.
+//!
+//! The materials come from the `.mtl` files named by `mtllib`, of which a small subset is
+//! mapped to the materials of the renderer:
+//!
+//! ```text
+//! newmtl plastic   # Kd gives the albedo of a lambertian material
+//! Kd 0.8 0.1 0.1
+//!
+//! newmtl steel     # illum 3 or a black Kd with a colored Ks is a metal, Ns sets the fuzz
+//! Ks 0.8 0.8 0.8
+//! Ns 800
+//!
+//! newmtl glass     # illum 4, 6, 7 or 9, or d below 1 is a dielectric, Ni is the refraction index
+//! d 0.1
+//! Ni 1.5
+//!
+//! newmtl lamp      # a colored Ke makes a light source
+//! Ke 10 10 10
+//! ```
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::color::{Color, WorkingSpace};
+use crate::geometry::*;
+use crate::materials::Material;
+use crate::maths::*;
+use crate::scene::{Albedo, HittableList, MaterialDescription};
+use crate::scene_format::{tokenize, SceneError, Token};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+// used for faces without a material
+const DEFAULT_MATERIAL: MaterialDescription = MaterialDescription::Lambertian {
+    albedo: Albedo::Color(Color::new(0.5, 0.5, 0.5)),
+};
+
+// one corner of a face, the indices are 0-based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaceVertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Face {
+    pub vertices: [FaceVertex; 3],
+    pub group: usize,
+    pub material: Option<String>,
+}
+
+// the contents of an OBJ file, polygons are already split into triangles
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ObjData {
+    pub positions: Vec<Point>,
+    pub uvs: Vec<Vec2>,
+    pub normals: Vec<Vec3>,
+    pub faces: Vec<Face>,
+    pub groups: Vec<String>,
+    pub material_libraries: Vec<String>,
+}
+
+// resolves a 1-based or negative (counted from the end) OBJ index
+fn parse_index(token: &Token, text: &str, count: usize, kind: &str) -> Result<usize, SceneError> {
+    let index: i64 = text
+        .parse()
+        .map_err(|_| token.error(format!("expected a {} index, found `{}`", kind, text)))?;
+    let resolved = if index < 0 {
+        count as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(token.error(format!(
+            "{} index {} is out of range, there are {} so far",
+            kind, index, count
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+fn numbers<const N: usize>(
+    keyword: &Token,
+    arguments: &[Token],
+    optional: usize,
+) -> Result<[f64; N], SceneError> {
+    if arguments.len() + optional < N {
+        return Err(keyword.error(format!(
+            "`{}` takes at least {} numbers, found {}",
+            keyword.text,
+            N - optional,
+            arguments.len()
+        )));
+    }
+    let mut values = [0.0; N];
+    for (value, token) in values.iter_mut().zip(arguments) {
+        *value = token.number()?;
+    }
+    Ok(values)
+}
+
+pub fn parse_obj(text: &str) -> Result<ObjData, SceneError> {
+    let mut obj = ObjData {
+        groups: vec!["default".to_string()],
+        ..Default::default()
+    };
+    let mut group = 0;
+    let mut material: Option<String> = None;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let tokens = tokenize(line, line_index + 1);
+        let Some((keyword, arguments)) = tokens.split_first() else {
+            continue;
+        };
+
+        match keyword.text {
+            "v" => {
+                let [x, y, z] = numbers(keyword, arguments, 0)?;
+                obj.positions.push(Point::new(x, y, z));
+            }
+            "vt" => {
+                let [u, v] = numbers(keyword, arguments, 1)?;
+                obj.uvs.push(Vec2::new(u, v));
+            }
+            "vn" => {
+                let [x, y, z] = numbers(keyword, arguments, 0)?;
+                obj.normals.push(Vec3::new(x, y, z).normalized());
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(keyword.error(format!(
+                        "a face needs at least 3 vertices, found {}",
+                        arguments.len()
+                    )));
+                }
+                // each corner is `v`, `v/vt`, `v//vn` or `v/vt/vn`
+                let corners = arguments
+                    .iter()
+                    .map(|token| {
+                        let mut parts = token.text.split('/');
+                        let position = parts.next().unwrap_or_default();
+                        let uv = parts.next().filter(|part| !part.is_empty());
+                        let normal = parts.next().filter(|part| !part.is_empty());
+                        Ok(FaceVertex {
+                            position: parse_index(token, position, obj.positions.len(), "vertex")?,
+                            uv: uv
+                                .map(|uv| parse_index(token, uv, obj.uvs.len(), "texture"))
+                                .transpose()?,
+                            normal: normal
+                                .map(|normal| {
+                                    parse_index(token, normal, obj.normals.len(), "normal")
+                                })
+                                .transpose()?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, SceneError>>()?;
+                // polygons are split into a fan around the first corner
+                for i in 1..corners.len() - 1 {
+                    obj.faces.push(Face {
+                        vertices: [corners[0], corners[i], corners[i + 1]],
+                        group,
+                        material: material.clone(),
+                    });
+                }
+            }
+            "g" | "o" => {
+                let name = arguments
+                    .iter()
+                    .map(|token| token.text)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let name = if name.is_empty() {
+                    "default".to_string()
+                } else {
+                    name
+                };
+                group = match obj.groups.iter().position(|existing| *existing == name) {
+                    Some(existing) => existing,
+                    None => {
+                        obj.groups.push(name);
+                        obj.groups.len() - 1
+                    }
+                };
+            }
+            "usemtl" => {
+                let [name, ..] = arguments else {
+                    return Err(keyword.error("`usemtl` needs a material name"));
+                };
+                material = Some(name.text.to_string());
+            }
+            "mtllib" => obj
+                .material_libraries
+                .extend(arguments.iter().map(|token| token.text.to_string())),
+            _ => {}
+        }
+    }
+    Ok(obj)
+}
+
+// the material properties of an MTL file that are used, with the defaults of the format
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Color,
+    emission: Color,
+    shininess: f64,
+    refraction_index: f64,
+    dissolve: f64,
+    illumination: Option<i64>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::black(),
+            emission: Color::black(),
+            shininess: 0.0,
+            refraction_index: 1.5,
+            dissolve: 1.0,
+            illumination: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn description(&self) -> MaterialDescription {
+        let transparent = self.dissolve < 1.0 || matches!(self.illumination, Some(4 | 6 | 7 | 9));
+        let reflective = self.illumination == Some(3)
+            || (self.diffuse.near_zero() && !self.specular.near_zero());
+        if !self.emission.near_zero() {
+            MaterialDescription::DiffuseLight {
+                emit: self.emission,
+            }
+        } else if transparent {
+            MaterialDescription::Dielectric {
+                refraction_index: self.refraction_index,
+            }
+        } else if reflective {
+            // Ns goes from 0 to 1000, a high exponent is a sharp reflection
+            MaterialDescription::Metal {
+                albedo: Albedo::Color(self.specular),
+                fuzz: (1.0 - self.shininess / 1000.0).clamp(0.0, 1.0),
+            }
+        } else {
+            MaterialDescription::Lambertian {
+                albedo: Albedo::Color(self.diffuse),
+            }
+        }
+    }
+}
+
+pub fn parse_mtl(text: &str) -> Result<Vec<(String, MaterialDescription)>, SceneError> {
+    let mut materials: Vec<(String, MtlMaterial)> = Vec::new();
+
+    for (line_index, line) in text.lines().enumerate() {
+        let tokens = tokenize(line, line_index + 1);
+        let Some((keyword, arguments)) = tokens.split_first() else {
+            continue;
+        };
+        if keyword.text == "newmtl" {
+            let [name, ..] = arguments else {
+                return Err(keyword.error("`newmtl` needs a material name"));
+            };
+            materials.push((name.text.to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let Some((_, material)) = materials.last_mut() else {
+            // statements before the first material have nothing to apply to
+            continue;
+        };
+        match keyword.text {
+            "Kd" => {
+                let [r, g, b] = numbers(keyword, arguments, 0)?;
+                material.diffuse = Color::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = numbers(keyword, arguments, 0)?;
+                material.specular = Color::new(r, g, b);
+            }
+            "Ke" => {
+                let [r, g, b] = numbers(keyword, arguments, 0)?;
+                material.emission = Color::new(r, g, b);
+            }
+            "Ns" => [material.shininess] = numbers(keyword, arguments, 0)?,
+            "Ni" => [material.refraction_index] = numbers(keyword, arguments, 0)?,
+            "d" => [material.dissolve] = numbers(keyword, arguments, 0)?,
+            "Tr" => {
+                let [transparency] = numbers(keyword, arguments, 0)?;
+                material.dissolve = 1.0 - transparency;
+            }
+            "illum" => {
+                let [illumination] = numbers(keyword, arguments, 0)?;
+                material.illumination = Some(illumination as i64);
+            }
+            _ => {}
+        }
+    }
+    Ok(materials
+        .into_iter()
+        .map(|(name, material)| (name, material.description()))
+        .collect())
+}
+
+// A triangle mesh with its own bounding volume hierarchy, so that it can be placed in
+// the scene like any other object
+pub struct Mesh {
+    triangles: Option<BvhNode>,
+    bbox: Aabb,
+    pub triangle_count: usize,
+    pub groups: Vec<String>,
+}
+
+impl Mesh {
+    // Loads an OBJ file and the MTL files it refers to, which are looked up next to it.
+    // The material of a face is looked up by its `usemtl` name in `materials` first, so
+    // that a scene can override the materials of a model, then in the MTL files. Faces
+    // without a known material use `fallback`. The colors of the MTL files are converted
+    // to the working space `space`.
+    pub fn load(
+        path: &Path,
+        materials: &HashMap<&str, Arc<dyn Material>>,
+        fallback: Option<Arc<dyn Material>>,
+        space: WorkingSpace,
+    ) -> Result<Self, SceneError> {
+        let in_file = |path: &Path| {
+            let path = path.to_path_buf();
+            move |error: SceneError| SceneError::InFile {
+                path,
+                error: Box::new(error),
+            }
+        };
+
+        let text = std::fs::read_to_string(path)
+            .map_err(SceneError::from)
+            .map_err(in_file(path))?;
+        let obj = parse_obj(&text).map_err(in_file(path))?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut library = HashMap::new();
+        for name in &obj.material_libraries {
+            let path = directory.join(name);
+            let text = std::fs::read_to_string(&path)
+                .map_err(SceneError::from)
+                .map_err(in_file(&path))?;
+            for (name, description) in parse_mtl(&text).map_err(in_file(&path))? {
+                library.entry(name).or_insert(description);
+            }
+        }
+
+        let fallback = fallback.unwrap_or_else(|| DEFAULT_MATERIAL.build(&HashMap::new(), space));
+        let mut built: HashMap<String, Arc<dyn Material>> = HashMap::new();
+        let material = |name: &str| -> Arc<dyn Material> {
+            if let Some(material) = materials.get(name) {
+                return Arc::clone(material);
+            }
+            match library.get(name) {
+                Some(description) => Arc::clone(
+                    built
+                        .entry(name.to_string())
+                        .or_insert_with(|| description.build(&HashMap::new(), space)),
+                ),
+                None => Arc::clone(&fallback),
+            }
+        };
+        Ok(Self::new(&obj, material))
+    }
+
+    // builds the triangles of the OBJ data with the materials returned by `material`
+    pub fn new(obj: &ObjData, mut material: impl FnMut(&str) -> Arc<dyn Material>) -> Self {
+        let mut list = HittableList::new();
+        for face in &obj.faces {
+            let material = material(face.material.as_deref().unwrap_or_default());
+            let vertices = face.vertices.map(|vertex| obj.positions[vertex.position]);
+            // skip degenerate triangles, they can never be hit
+            if (vertices[1] - vertices[0])
+                .cross(vertices[2] - vertices[0])
+                .near_zero()
+            {
+                continue;
+            }
+            let mut triangle = Triangle::new(vertices, material);
+            if let [Some(a), Some(b), Some(c)] = face.vertices.map(|vertex| vertex.normal) {
+                triangle.normals = Some([obj.normals[a], obj.normals[b], obj.normals[c]]);
+            }
+            if let [Some(a), Some(b), Some(c)] = face.vertices.map(|vertex| vertex.uv) {
+                triangle.uvs = Some([obj.uvs[a], obj.uvs[b], obj.uvs[c]]);
+            }
+            list.add(Box::new(triangle));
+        }
+
+        let triangle_count = list.object_count();
+        let bbox = list.bounding_box();
+        Self {
+            triangles: BvhNode::new(list),
+            bbox,
+            triangle_count,
+            groups: obj.groups.clone(),
+        }
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        self.triangles.as_ref()?.hit(ray, interval)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD: &str = "
+        mtllib quad.mtl
+        v 0 0 0
+        v 1 0 0
+        v 1 1 0
+        v 0 1 0
+        vt 0 0
+        vt 1 0
+        vt 1 1
+        vt 0 1
+        vn 0 0 1
+        g front
+        usemtl red
+        f 1/1/1 2/2/1 3/3/1 4/4/1
+        g back
+        f -4//1 -2//1 -3//1
+    ";
+
+    #[test]
+    fn parses_groups_materials_and_splits_polygons() {
+        let obj = parse_obj(QUAD).unwrap();
+        assert_eq!(obj.positions.len(), 4);
+        assert_eq!(obj.groups, vec!["default", "front", "back"]);
+        assert_eq!(obj.material_libraries, vec!["quad.mtl"]);
+        assert_eq!(obj.faces.len(), 3);
+        assert_eq!(
+            obj.faces[1].vertices.map(|vertex| vertex.position),
+            [0, 2, 3]
+        );
+        assert_eq!(obj.faces[1].group, 1);
+        assert_eq!(obj.faces[2].group, 2);
+        // usemtl stays active across groups
+        assert_eq!(obj.faces[2].material.as_deref(), Some("red"));
+        assert_eq!(obj.faces[2].vertices[0].uv, None);
+        assert_eq!(obj.faces[2].vertices[0].normal, Some(0));
+    }
+
+    #[test]
+    fn reports_invalid_indices() {
+        match parse_obj("v 0 0 0\nv 1 0 0\nf 1 2  3") {
+            Err(SceneError::Parse { line, column, .. }) => assert_eq!((line, column), (3, 8)),
+            other => panic!("expected a parse error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn maps_mtl_materials() {
+        let materials = parse_mtl(
+            "
+            newmtl red
+            Kd 1 0 0
+            newmtl steel
+            Kd 0 0 0
+            Ks 0.5 0.5 0.5
+            Ns 1000
+            newmtl glass
+            illum 4
+            Ni 1.3
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            materials,
+            vec![
+                (
+                    "red".to_string(),
+                    MaterialDescription::Lambertian {
+                        albedo: Albedo::Color(Color::new(1.0, 0.0, 0.0))
+                    }
+                ),
+                (
+                    "steel".to_string(),
+                    MaterialDescription::Metal {
+                        albedo: Albedo::Color(Color::new(0.5, 0.5, 0.5)),
+                        fuzz: 0.0
+                    }
+                ),
+                (
+                    "glass".to_string(),
+                    MaterialDescription::Dielectric {
+                        refraction_index: 1.3
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn mesh_hits_interpolate_uvs() {
+        let obj = parse_obj(QUAD).unwrap();
+        let mesh = Mesh::new(&obj, |_| {
+            DEFAULT_MATERIAL.build(&HashMap::new(), WorkingSpace::default())
+        });
+        assert_eq!(mesh.triangle_count, 3);
+
+        let ray = Ray::new(Point::new(0.25, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = mesh
+            .hit(&ray, &Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        assert!((record.t - 1.0).abs() < 1e-9);
+        assert!((record.uv - Vec2::new(0.25, 0.75)).length() < 1e-9);
+        assert!(record.front_face);
+        assert_eq!(record.normal, Vec3::new(0.0, 0.0, 1.0));
+    }
+}
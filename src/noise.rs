@@ -0,0 +1,108 @@
+use crate::maths::{Point, Vec3};
+use crate::random::{random_vec3_unit_with, seeded_rng};
+use rand::seq::SliceRandom;
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise after Ken Perlin: every point of the integer lattice gets a random
+// gradient, and the noise in between is interpolated from them. The same seed always
+// gives the same noise
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permutation_x: Vec<usize>,
+    permutation_y: Vec<usize>,
+    permutation_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = seeded_rng(seed);
+        let gradients = (0..POINT_COUNT)
+            .map(|_| random_vec3_unit_with(&mut rng))
+            .collect();
+        let mut permutation = || {
+            let mut permutation: Vec<usize> = (0..POINT_COUNT).collect();
+            permutation.shuffle(&mut rng);
+            permutation
+        };
+        Self {
+            gradients,
+            permutation_x: permutation(),
+            permutation_y: permutation(),
+            permutation_z: permutation(),
+        }
+    }
+
+    // noise between -1 and 1, smooth and zero at all integer points
+    pub fn noise(&self, point: Point) -> f64 {
+        let (u, v, w) = (
+            point.x - point.x.floor(),
+            point.y - point.y.floor(),
+            point.z - point.z.floor(),
+        );
+        let (i, j, k) = (
+            point.x.floor() as i64,
+            point.y.floor() as i64,
+            point.z.floor() as i64,
+        );
+
+        // the hermite cubic removes the grid artifacts of linear interpolation
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let (uu, vv, ww) = (smooth(u), smooth(v), smooth(w));
+
+        let index = |value: i64| (value & (POINT_COUNT as i64 - 1)) as usize;
+        let mut accumulated = 0.0;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[self.permutation_x[index(i + di)]
+                        ^ self.permutation_y[index(j + dj)]
+                        ^ self.permutation_z[index(k + dk)]];
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+                    accumulated += (fi * uu + (1.0 - fi) * (1.0 - uu))
+                        * (fj * vv + (1.0 - fj) * (1.0 - vv))
+                        * (fk * ww + (1.0 - fk) * (1.0 - ww))
+                        * gradient.dot(weight);
+                }
+            }
+        }
+        accumulated
+    }
+
+    // fractal sum of the noise at doubling frequencies and halving amplitudes
+    pub fn turbulence(&self, point: Point, octaves: usize) -> f64 {
+        let mut accumulated = 0.0;
+        let mut point = point;
+        let mut weight = 1.0;
+        for _ in 0..octaves {
+            accumulated += weight * self.noise(point);
+            weight *= 0.5;
+            point = point * 2.0;
+        }
+        accumulated.abs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_is_reproducible_from_the_seed() {
+        let point = Point::new(1.3, -2.7, 0.4);
+        assert_eq!(Perlin::new(7).noise(point), Perlin::new(7).noise(point));
+        assert_ne!(Perlin::new(7).noise(point), Perlin::new(8).noise(point));
+    }
+
+    #[test]
+    fn noise_is_bounded_and_zero_on_the_lattice() {
+        let perlin = Perlin::new(0);
+        assert_eq!(perlin.noise(Point::new(3.0, -1.0, 5.0)), 0.0);
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let noise = perlin.noise(Point::new(t, t * 0.71, -t * 1.3));
+            assert!((-1.0..=1.0).contains(&noise));
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Probability densities over directions, used to importance sample the rays of a path.
+
+use crate::background::Environment;
+use crate::geometry::{Hittable, Ray};
+use crate::maths::*;
+use crate::random::{random_cosine_direction, random_double, random_vec3_unit};
+use std::f64::consts::PI;
+
+// A distribution of directions. `generate` draws a direction from it and `value` is the
+// density of a direction per unit solid angle.
+pub trait Pdf {
+    fn value(&self, direction: Vec3) -> f64;
+    fn generate(&self) -> Vec3;
+}
+
+// directions around the normal with a density proportional to the cosine, which matches
+// the light a Lambertian surface scatters
+pub struct CosinePdf {
+    normal: Vec3,
+    tangent: Vec3,
+    bitangent: Vec3,
+}
+
+impl CosinePdf {
+    pub fn new(normal: Vec3) -> Self {
+        let normal = normal.normalized();
+        let (tangent, bitangent) = normal.perpendicular_basis();
+        Self {
+            normal,
+            tangent,
+            bitangent,
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cosine = direction.normalized().dot(self.normal);
+        (cosine / PI).max(0.0)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let local = random_cosine_direction();
+        self.tangent * local.x + self.bitangent * local.y + self.normal * local.z
+    }
+}
+
+// all directions alike
+pub struct SpherePdf;
+
+impl Pdf for SpherePdf {
+    fn value(&self, _direction: Vec3) -> f64 {
+        1.0 / (4.0 * PI)
+    }
+
+    fn generate(&self) -> Vec3 {
+        random_vec3_unit()
+    }
+}
+
+// directions from a point towards an object, usually a light, see `Hittable::random`
+pub struct HittablePdf<'a> {
+    object: &'a dyn Hittable,
+    origin: Point,
+    time: f64,
+}
+
+impl<'a> HittablePdf<'a> {
+    pub fn new(object: &'a dyn Hittable, origin: Point, time: f64) -> Self {
+        Self {
+            object,
+            origin,
+            time,
+        }
+    }
+}
+
+impl Pdf for HittablePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.object
+            .pdf_value(&Ray::with_time(self.origin, direction, self.time))
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.object.random(self.origin, self.time)
+    }
+}
+
+// directions towards the bright parts of the environment, see `Environment::random`
+pub struct EnvironmentPdf<'a> {
+    environment: &'a dyn Environment,
+}
+
+impl<'a> EnvironmentPdf<'a> {
+    pub fn new(environment: &'a dyn Environment) -> Self {
+        Self { environment }
+    }
+}
+
+impl Pdf for EnvironmentPdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.environment.pdf_value(direction)
+    }
+
+    fn generate(&self) -> Vec3 {
+        self.environment.random()
+    }
+}
+
+// draws from either of two densities with equal chance
+pub struct MixturePdf<'a> {
+    first: Box<dyn Pdf + 'a>,
+    second: Box<dyn Pdf + 'a>,
+}
+
+impl<'a> MixturePdf<'a> {
+    pub fn new(first: Box<dyn Pdf + 'a>, second: Box<dyn Pdf + 'a>) -> Self {
+        Self { first, second }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        0.5 * (self.first.value(direction) + self.second.value(direction))
+    }
+
+    fn generate(&self) -> Vec3 {
+        if random_double() < 0.5 {
+            self.first.generate()
+        } else {
+            self.second.generate()
+        }
+    }
+}
+
+// The power heuristic weight of a sample that was drawn with the first density, when the
+// second strategy could have produced it as well. The weights of both strategies add up to
+// one, and samples that only one of them is good at are mostly left to that one.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // integrates the density over the sphere of directions with uniform samples
+    fn total_probability(pdf: &dyn Pdf) -> f64 {
+        let samples = 200_000;
+        let sum: f64 = (0..samples).map(|_| pdf.value(random_vec3_unit())).sum();
+        sum / samples as f64 * 4.0 * PI
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let cosine = CosinePdf::new(Vec3::new(1.0, 2.0, -0.5));
+        assert!((total_probability(&cosine) - 1.0).abs() < 0.02);
+        assert!((total_probability(&SpherePdf) - 1.0).abs() < 1e-9);
+        let mixture = MixturePdf::new(Box::new(cosine), Box::new(SpherePdf));
+        assert!((total_probability(&mixture) - 1.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn cosine_samples_stay_in_the_hemisphere() {
+        let normal = Vec3::new(0.0, -1.0, 1.0).normalized();
+        let pdf = CosinePdf::new(normal);
+        let mean = (0..10_000).fold(Vec3::zero(), |sum, _| {
+            let direction = pdf.generate();
+            assert!(direction.dot(normal) >= 0.0);
+            assert!((direction.length() - 1.0).abs() < 1e-9);
+            sum + direction
+        }) / 10_000.0;
+        // the mean cosine of the distribution is 2/3
+        assert!((mean.dot(normal) - 2.0 / 3.0).abs() < 0.02);
+    }
+
+    #[test]
+    fn power_heuristic_weights_add_up_to_one() {
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+        assert_eq!(power_heuristic(1.0, 0.0), 1.0);
+        let (a, b) = (0.3, 2.5);
+        assert!((power_heuristic(a, b) + power_heuristic(b, a) - 1.0).abs() < 1e-12);
+    }
+}
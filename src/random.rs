@@ -1,5 +1,6 @@
 use crate::maths::Vec3;
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use rand_distr::StandardNormal;
 
 pub fn random_double() -> f64 {
@@ -10,6 +11,21 @@ pub fn random_double_range(min: f64, max: f64) -> f64 {
     rand::thread_rng().gen_range(min..max)
 }
 
+// a generator with a fixed seed, for results that have to be the same in every run
+pub fn seeded_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+// uniformly distributed direction, drawn from the given generator
+pub fn random_vec3_unit_with(rng: &mut impl Rng) -> Vec3 {
+    Vec3 {
+        x: rng.sample(StandardNormal),
+        y: rng.sample(StandardNormal),
+        z: rng.sample(StandardNormal),
+    }
+    .normalized()
+}
+
 pub fn random_vec3() -> Vec3 {
     Vec3::new(random_double(), random_double(), random_double())
 }
@@ -23,14 +39,21 @@ pub fn random_vec3_min_max(min: f64, max: f64) -> Vec3 {
 
 pub fn random_vec3_unit() -> Vec3 {
     // generate xyz from gaussian distribution
-    let mut rng = rand::thread_rng();
+    random_vec3_unit_with(&mut rand::thread_rng())
+}
 
-    Vec3 {
-        x: rng.sample(StandardNormal),
-        y: rng.sample(StandardNormal),
-        z: rng.sample(StandardNormal),
+// uniformly distributed point in the unit disk in the xy plane
+pub fn random_vec3_in_unit_disk() -> Vec3 {
+    loop {
+        let p = Vec3::new(
+            random_double_range(-1.0, 1.0),
+            random_double_range(-1.0, 1.0),
+            0.0,
+        );
+        if p.length_squared() < 1.0 {
+            return p;
+        }
     }
-    .normalized()
 }
 
 pub fn random_vec3_on_hemisphere(normal: Vec3) -> Vec3 {
@@ -41,3 +64,13 @@ pub fn random_vec3_on_hemisphere(normal: Vec3) -> Vec3 {
         -in_unit_sphere
     }
 }
+
+// direction in the hemisphere around +z with a density proportional to the cosine of its
+// angle to z, which is cos / pi
+pub fn random_cosine_direction() -> Vec3 {
+    let r1 = random_double();
+    let r2 = random_double();
+    let phi = 2.0 * std::f64::consts::PI * r1;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    Vec3::new(cos_phi * r2.sqrt(), sin_phi * r2.sqrt(), (1.0 - r2).sqrt())
+}
@@ -0,0 +1,242 @@
+use crate::bvh::BvhStats;
+use crate::camera::CameraSettings;
+use crate::renderer::{FrameBuffer, RenderParameters, Renderer};
+use crate::scene::World;
+use crate::tonemap::ToneMapping;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// commands sent from the UI to the render thread
+pub enum RenderCommand {
+    Start,
+    Cancel,
+    Restart(RenderParameters),
+    MoveCamera(CameraSettings, RenderParameters),
+    SetWorld(Arc<World>, RenderParameters),
+    SetToneMapping(ToneMapping),
+    Shutdown,
+}
+
+// the state of the render at the time the snapshot was taken
+#[derive(Clone)]
+pub struct RenderSnapshot {
+    pub frame: FrameBuffer,
+    pub parameters: RenderParameters,
+    pub progress: f64,
+    pub samples_taken: usize,
+    pub render_duration: Duration,
+    pub object_count: usize,
+    pub bvh_stats: BvhStats,
+    pub tone_mapping: ToneMapping,
+    pub exposure: f64, // in EV, including auto exposure
+    pub is_running: bool,
+}
+
+impl RenderSnapshot {
+    fn new(renderer: &Renderer, parameters: RenderParameters, is_running: bool) -> Self {
+        Self {
+            frame: renderer.get_color_buffer().clone(),
+            parameters,
+            progress: renderer.get_progress_percentage(),
+            samples_taken: renderer.get_samples_taken(),
+            render_duration: renderer.get_render_duration(),
+            object_count: renderer.get_scene_object_count(),
+            bvh_stats: renderer.get_bvh_stats(),
+            tone_mapping: renderer.get_tone_mapping(),
+            exposure: renderer.get_exposure(),
+            is_running,
+        }
+    }
+}
+
+// Runs a renderer on a background thread so that rendering never blocks the UI.
+// The UI sends commands and receives snapshots of the frame buffer over channels.
+pub struct RenderService {
+    commands: Sender<RenderCommand>,
+    snapshots: Receiver<RenderSnapshot>,
+    cancel_flag: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderService {
+    pub fn new(parameters: RenderParameters, camera: CameraSettings, world: Arc<World>) -> Self {
+        let (command_sender, command_receiver) = channel();
+        let (snapshot_sender, snapshot_receiver) = channel();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+
+        let thread_cancel_flag = Arc::clone(&cancel_flag);
+        let thread = std::thread::Builder::new()
+            .name("render".to_string())
+            .spawn(move || {
+                RenderThread::new(
+                    parameters,
+                    camera,
+                    world,
+                    thread_cancel_flag,
+                    snapshot_sender,
+                )
+                .run(command_receiver)
+            })
+            .expect("failed to spawn render thread");
+
+        Self {
+            commands: command_sender,
+            snapshots: snapshot_receiver,
+            cancel_flag,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn start(&self) {
+        self.send(RenderCommand::Start);
+    }
+
+    pub fn cancel(&self) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+        self.send(RenderCommand::Cancel);
+    }
+
+    pub fn restart(&self, parameters: RenderParameters) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+        self.send(RenderCommand::Restart(parameters));
+    }
+
+    // moves the camera and restarts the render from the new viewpoint
+    pub fn move_camera(&self, camera: CameraSettings, parameters: RenderParameters) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+        self.send(RenderCommand::MoveCamera(camera, parameters));
+    }
+
+    // replaces the world and restarts the render with it
+    pub fn set_world(&self, world: Arc<World>, parameters: RenderParameters) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+        self.send(RenderCommand::SetWorld(world, parameters));
+    }
+
+    // changes how the image is displayed, without rendering it again
+    pub fn set_tone_mapping(&self, tone_mapping: ToneMapping) {
+        self.send(RenderCommand::SetToneMapping(tone_mapping));
+    }
+
+    // returns the most recent snapshot, if any arrived since the last call
+    pub fn latest_snapshot(&self) -> Option<RenderSnapshot> {
+        self.snapshots.try_iter().last()
+    }
+
+    fn send(&self, command: RenderCommand) {
+        // the render thread only exits on shutdown, so sending can not fail before that
+        let _ = self.commands.send(command);
+    }
+}
+
+impl Drop for RenderService {
+    fn drop(&mut self) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+        self.send(RenderCommand::Shutdown);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct RenderThread {
+    renderer: Renderer,
+    parameters: RenderParameters,
+    is_running: bool,
+    // shared with the service so that a command can abort the render step in flight
+    cancel_flag: Arc<AtomicBool>,
+    snapshots: Sender<RenderSnapshot>,
+}
+
+impl RenderThread {
+    fn new(
+        parameters: RenderParameters,
+        camera: CameraSettings,
+        world: Arc<World>,
+        cancel_flag: Arc<AtomicBool>,
+        snapshots: Sender<RenderSnapshot>,
+    ) -> Self {
+        Self {
+            renderer: Renderer::with_cancel_flag(
+                parameters,
+                &camera,
+                world,
+                Arc::clone(&cancel_flag),
+            ),
+            parameters,
+            is_running: false,
+            cancel_flag,
+            snapshots,
+        }
+    }
+
+    fn run(mut self, commands: Receiver<RenderCommand>) {
+        loop {
+            let received = if self.is_running {
+                commands.try_recv()
+            } else {
+                // nothing to do, so block until the UI wants something
+                commands.recv().map_err(|_| TryRecvError::Disconnected)
+            };
+            match received {
+                Ok(command) => {
+                    // a command that arrives after this point sets the flag again
+                    // and aborts the next render step
+                    self.cancel_flag.store(false, Ordering::Relaxed);
+                    if !self.handle_command(command) {
+                        return;
+                    }
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => {}
+            }
+
+            if self.renderer.render_step() > 0 {
+                self.is_running = !self.renderer.is_finished();
+                self.send_snapshot();
+            }
+        }
+    }
+
+    // returns false if the thread should shut down
+    fn handle_command(&mut self, command: RenderCommand) -> bool {
+        match command {
+            RenderCommand::Start => {
+                self.is_running = !self.renderer.is_finished();
+            }
+            RenderCommand::Cancel => {
+                self.is_running = false;
+            }
+            RenderCommand::Restart(parameters) => {
+                self.parameters = parameters;
+                self.renderer.restart(parameters);
+                self.is_running = true;
+            }
+            RenderCommand::MoveCamera(camera, parameters) => {
+                self.parameters = parameters;
+                self.renderer.set_camera(&camera, parameters);
+                self.is_running = true;
+            }
+            RenderCommand::SetWorld(world, parameters) => {
+                self.parameters = parameters;
+                self.renderer.set_world(world, parameters);
+                self.is_running = true;
+            }
+            RenderCommand::SetToneMapping(tone_mapping) => {
+                self.renderer.set_tone_mapping(tone_mapping);
+            }
+            RenderCommand::Shutdown => return false,
+        }
+        self.send_snapshot();
+        true
+    }
+
+    fn send_snapshot(&self) {
+        let snapshot = RenderSnapshot::new(&self.renderer, self.parameters, self.is_running);
+        let _ = self.snapshots.send(snapshot);
+    }
+}
@@ -1,20 +1,70 @@
-use crate::camera::Camera;
-use crate::color::Color;
+use crate::bvh::BvhStats;
+use crate::camera::{Camera, CameraSettings, DepthPolicy};
+use crate::image::HdrImage;
 use crate::maths::*;
 use crate::random::*;
-use crate::scene::HittableList;
+use crate::scene::World;
 use crate::terminal::*;
+use crate::tonemap::{auto_exposure, ToneMapping};
+use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// width divided by height of a terminal cell
+const PIXEL_ASPECT_RATIO: f64 = 10.0 / 20.0;
+
+// how the samples of a pixel are distributed over the render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RenderMode {
+    // every pass adds the given number of samples to all pixels, so the whole
+    // image improves over time
+    Progressive { samples_per_pass: usize },
+    // takes all samples of a line before moving on to the next one
+    FinalQuality,
+}
+
+// parameters that define a render, changing them requires a restart of the render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RenderParameters {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize, // total samples for each pixel when the render is done
+    pub mode: RenderMode,
+    pub depth: DepthPolicy, // how many bounces the paths of light take
+}
+
+impl Default for RenderParameters {
+    fn default() -> Self {
+        Self {
+            width: 192,
+            height: 72,
+            samples_per_pixel: 512,
+            mode: RenderMode::Progressive {
+                samples_per_pass: SUBPIXEL_X * SUBPIXEL_Y,
+            },
+            depth: DepthPolicy::default(),
+        }
+    }
+}
 
 pub struct Renderer {
     color_buffer: FrameBuffer,
-    next_line_to_process: usize,
+    accumulation_buffer: Vec<RenderPixel>, // HDR sums of all samples taken so far
+    samples_per_pass: usize,
+    samples_taken: usize,         // samples per pixel of all completed passes
+    next_line_to_process: usize,  // within the current pass
+    cancel_flag: Arc<AtomicBool>, // aborts the lines currently in flight when set
     render_duration: std::time::Duration,
+    camera_settings: CameraSettings,
     camera: Camera,
     samples_per_pixel: usize, // Count of random samples for each pixel
-    max_depth: i32,           // Maximum number of ray bounces into scene
-    world: HittableList,
+    depth: DepthPolicy,
+    world: Arc<World>,
+    tone_mapping: ToneMapping,
+    auto_exposure: f64, // in EV, updated after every pass while auto exposure is on
 }
 
+#[derive(Clone)]
 pub struct FrameBuffer {
     pub width: usize,
     pub height: usize,
@@ -53,34 +103,232 @@ impl Renderer {
         self.render_duration
     }
 
-    pub fn new(width: usize, height: usize) -> Renderer {
-        let color_buffer = FrameBuffer::new(width, height);
-
-        let pixel_aspect_ratio = 10.0 / 20.0;
-        let focal_length = 1.0;
-        let camera_center = Vec3::new(0.0, 0.0, 0.0);
+    pub fn new(
+        parameters: RenderParameters,
+        camera_settings: &CameraSettings,
+        world: Arc<World>,
+    ) -> Renderer {
+        Renderer::with_cancel_flag(
+            parameters,
+            camera_settings,
+            world,
+            Arc::new(AtomicBool::new(false)),
+        )
+    }
 
-        Renderer {
-            color_buffer,
+    // the cancel flag can be set from any thread to abort the current render step early
+    pub fn with_cancel_flag(
+        parameters: RenderParameters,
+        camera_settings: &CameraSettings,
+        world: Arc<World>,
+        cancel_flag: Arc<AtomicBool>,
+    ) -> Renderer {
+        let mut renderer = Renderer {
+            color_buffer: FrameBuffer::new(0, 0),
+            accumulation_buffer: Vec::new(),
+            samples_per_pass: 1,
+            samples_taken: 0,
             next_line_to_process: 0,
+            cancel_flag,
             render_duration: std::time::Duration::from_micros(0),
-            camera: Camera::new(
-                width as f64,
-                height as f64,
-                pixel_aspect_ratio,
-                focal_length,
-                camera_center,
-            ),
-            samples_per_pixel: 512,
-            max_depth: 10,
-            world: HittableList::default(),
-        }
+            camera_settings: *camera_settings,
+            camera: Camera::new(1.0, 1.0, PIXEL_ASPECT_RATIO, camera_settings),
+            samples_per_pixel: 0,
+            depth: DepthPolicy::default(),
+            world,
+            tone_mapping: ToneMapping::default(),
+            auto_exposure: 0.0,
+        };
+        renderer.restart(parameters);
+        renderer
+    }
+
+    // discards the rendered image and starts over with new parameters, the scene is kept
+    pub fn restart(&mut self, parameters: RenderParameters) {
+        let RenderParameters {
+            width,
+            height,
+            samples_per_pixel,
+            mode,
+            depth,
+        } = parameters;
+        // every round of samples covers all subpixels of a pixel once, so the counts are
+        // rounded up to whole rounds and the average divides by what was really traced
+        let round = SUBPIXEL_X * SUBPIXEL_Y;
+        let samples_per_pixel = samples_per_pixel.div_ceil(round) * round;
+        let samples_per_pass = match mode {
+            RenderMode::Progressive { samples_per_pass } => {
+                samples_per_pass.div_ceil(round) * round
+            }
+            RenderMode::FinalQuality => samples_per_pixel,
+        };
+
+        self.color_buffer = FrameBuffer::new(width, height);
+        self.accumulation_buffer = vec![RenderPixel::default(); width * height];
+        self.samples_per_pass = samples_per_pass.max(round);
+        self.samples_taken = 0;
+        self.next_line_to_process = 0;
+        self.render_duration = std::time::Duration::from_micros(0);
+        self.samples_per_pixel = samples_per_pixel;
+        self.depth = depth;
+        self.camera = Camera::new(
+            width as f64,
+            height as f64,
+            PIXEL_ASPECT_RATIO,
+            &self.camera_settings,
+        );
+    }
+
+    // moves the camera, which restarts the render
+    pub fn set_camera(&mut self, camera_settings: &CameraSettings, parameters: RenderParameters) {
+        self.camera_settings = *camera_settings;
+        self.restart(parameters);
+    }
+
+    // changes how the image is displayed, the samples are kept
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping) {
+        self.tone_mapping = tone_mapping;
+        self.update_auto_exposure();
+        self.update_color_buffer();
+    }
+
+    pub fn get_tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    // the exposure in EV that the image is displayed with, including auto exposure
+    pub fn get_exposure(&self) -> f64 {
+        self.tone_mapping.total_exposure(self.auto_exposure)
+    }
+
+    // renders a different world, e.g. the same scene under another sky
+    pub fn set_world(&mut self, world: Arc<World>, parameters: RenderParameters) {
+        self.world = world;
+        self.restart(parameters);
     }
 
     pub fn get_progress_percentage(&self) -> f64 {
         let (_width, height) = self.get_color_buffer_size();
         let progress_interval = Interval { min: 0.0, max: 1.0 };
-        progress_interval.clamp(self.next_line_to_process as f64 / height as f64)
+        let samples_in_pass = self.samples_in_current_pass() * self.next_line_to_process;
+        let samples_done = self.samples_taken * height + samples_in_pass;
+        progress_interval.clamp(samples_done as f64 / (self.samples_per_pixel * height) as f64)
+    }
+
+    // number of samples per pixel that all pixels have received so far
+    pub fn get_samples_taken(&self) -> usize {
+        self.samples_taken
+    }
+
+    // number of lines from the top that have received all of their samples
+    pub fn get_completed_lines(&self) -> usize {
+        if self.is_finished() {
+            self.color_buffer.height
+        } else if self.samples_taken + self.samples_in_current_pass() >= self.samples_per_pixel {
+            // the lines of the last pass are done once it has passed them
+            self.next_line_to_process
+        } else {
+            0
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.samples_taken >= self.samples_per_pixel
+    }
+
+    // the last pass only takes the samples that are left
+    fn samples_in_current_pass(&self) -> usize {
+        self.samples_per_pass
+            .min(self.samples_per_pixel.saturating_sub(self.samples_taken))
+    }
+
+    // the number of samples that the pixels of a line have received, the lines before
+    // next_line_to_process already have the samples of the current pass
+    fn line_samples(&self, y: usize) -> usize {
+        if y < self.next_line_to_process {
+            self.samples_taken + self.samples_in_current_pass()
+        } else {
+            self.samples_taken
+        }
+    }
+
+    // the running averages of the subpixels of a pixel in linear sRGB, if it has any samples
+    fn average_pixel(&self, x: usize, y: usize) -> Option<RenderPixel> {
+        let samples = self.line_samples(y);
+        (samples > 0).then(|| {
+            self.output_pixel(
+                self.accumulation_buffer[y * self.color_buffer.width + x] * (1.0 / samples as f64),
+            )
+        })
+    }
+
+    // converts the colors of a pixel from the working space of the world to linear sRGB,
+    // which is what the terminal and the image files show
+    fn output_pixel(&self, pixel: RenderPixel) -> RenderPixel {
+        let space = self.world.working_space;
+        pixel.map(|color| space.to_srgb(color))
+    }
+
+    // the running average of every subpixel, i.e. the image at full resolution
+    // with SUBPIXEL_X x SUBPIXEL_Y pixels for every terminal cell
+    pub fn get_hdr_image(&self) -> HdrImage {
+        let (width, height) = self.get_color_buffer_size();
+        let mut image = HdrImage::new(width * SUBPIXEL_X, height * SUBPIXEL_Y);
+        for y in 0..height {
+            for x in 0..width {
+                let Some(pixel) = self.average_pixel(x, y) else {
+                    continue;
+                };
+                for subpixel_y in 0..SUBPIXEL_Y {
+                    for subpixel_x in 0..SUBPIXEL_X {
+                        image.set_pixel(
+                            x * SUBPIXEL_X + subpixel_x,
+                            y * SUBPIXEL_Y + subpixel_y,
+                            pixel.get_color(subpixel_x, subpixel_y),
+                        );
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    // measures the image for auto exposure, if it is on
+    fn update_auto_exposure(&mut self) {
+        if !self.tone_mapping.auto_exposure || self.samples_taken == 0 {
+            return;
+        }
+        let (width, height) = self.get_color_buffer_size();
+        let pixels = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)));
+        let colors = pixels
+            .filter_map(|(x, y)| self.average_pixel(x, y))
+            .flat_map(|pixel| {
+                (0..SUBPIXEL_Y)
+                    .flat_map(move |y| (0..SUBPIXEL_X).map(move |x| pixel.get_color(x, y)))
+            });
+        self.auto_exposure = auto_exposure(colors);
+    }
+
+    // shows all pixels again, e.g. with a new exposure
+    fn update_color_buffer(&mut self) {
+        let (width, height) = self.get_color_buffer_size();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = match self.average_pixel(x, y) {
+                    Some(pixel) => self.display_pixel(pixel),
+                    None => TerminalPixel::default(),
+                };
+                *self.color_buffer.get_pixel_mut(x, y) = pixel;
+            }
+        }
+    }
+
+    // the terminal character for the average colors of a pixel. The colors are tone mapped
+    // first, so that the subpixels are told apart the way they are seen
+    fn display_pixel(&self, pixel: RenderPixel) -> TerminalPixel {
+        pixel
+            .map(|color| self.tone_mapping.apply(color, self.auto_exposure))
+            .to_terminal_pixel()
     }
 
     pub fn get_color_buffer(&self) -> &FrameBuffer {
@@ -88,89 +336,199 @@ impl Renderer {
     }
 
     pub fn get_scene_object_count(&self) -> usize {
-        self.world.object_count()
+        self.world.object_count
     }
 
-    pub fn render_step(&mut self) {
+    pub fn get_bvh_stats(&self) -> BvhStats {
+        self.world.bvh_stats
+    }
+
+    // renders lines for a limited time and returns the number of completed lines
+    pub fn render_step(&mut self) -> usize {
         let mut lines_processed = 0;
 
-        // render line-by line, but only for a maximum of 15ms
+        // render batches of lines in parallel, but only for a maximum of 15ms.
+        // each batch hands one line to every worker thread of the pool
+        let lines_per_batch = rayon::current_num_threads().max(1);
         let start = std::time::Instant::now();
-        while start.elapsed() < std::time::Duration::from_millis(15) {
-            if self.next_line_to_process < self.color_buffer.height {
-                self.render_line(self.next_line_to_process);
-                self.next_line_to_process += 1;
-                lines_processed += 1;
-            } else {
+        while start.elapsed() < std::time::Duration::from_millis(15) && !self.is_finished() {
+            let first_line = self.next_line_to_process;
+            let last_line = (first_line + lines_per_batch).min(self.color_buffer.height);
+            if !self.render_lines(first_line, last_line) {
+                // cancelled, the batch is rendered again when the render is resumed
                 break;
             }
+            self.next_line_to_process = last_line;
+            lines_processed += last_line - first_line;
+
+            // start the next pass over the image
+            if self.next_line_to_process >= self.color_buffer.height {
+                self.samples_taken += self.samples_in_current_pass();
+                self.next_line_to_process = 0;
+                if self.tone_mapping.auto_exposure {
+                    self.update_auto_exposure();
+                    self.update_color_buffer();
+                }
+            }
         }
         // only update render duration if we actually rendered something
         if lines_processed > 0 {
             self.render_duration += start.elapsed();
         }
+        lines_processed
     }
 
-    fn render_line(&mut self, line_index: usize) {
-        let row = &mut self.color_buffer.get_rows_mut()[line_index];
-
-        for (xi, pixel) in row.iter_mut().enumerate() {
-            let x: f64 = xi as f64;
-            let y: f64 = line_index as f64;
-            let sample_scale = 1.0 / self.samples_per_pixel as f64;
-
-            // note: no gamma correction needed for now because we directly display without
-            // saving to a gamma file format
-            *pixel = Renderer::render_pixel_samples(
-                self.samples_per_pixel,
-                x,
-                y,
-                sample_scale,
-                &self.camera,
-                &self.world,
-                self.max_depth,
-            );
+    // renders one pass of the lines in [first_line, last_line) in parallel on the rayon
+    // thread pool and updates the displayed pixels from the running average.
+    // returns false if the render was cancelled before all lines were completed
+    fn render_lines(&mut self, first_line: usize, last_line: usize) -> bool {
+        let width = self.color_buffer.width;
+        let samples = self.samples_in_current_pass();
+        let depth = &self.depth;
+        let camera = &self.camera;
+        let world = self.world.as_ref();
+        let cancel_flag = &self.cancel_flag;
+
+        // the samples are only added to the accumulation buffer once the whole batch is done,
+        // so that a cancelled batch can simply be rendered again
+        let new_samples: Option<Vec<Vec<RenderPixel>>> = (first_line..last_line)
+            .into_par_iter()
+            .map(|line_index| {
+                let y = line_index as f64;
+                let mut line = Vec::with_capacity(width);
+                for xi in 0..width {
+                    if cancel_flag.load(Ordering::Relaxed) {
+                        return None;
+                    }
+                    line.push(Renderer::render_pixel_samples(
+                        samples, xi as f64, y, camera, world, depth,
+                    ));
+                }
+                Some(line)
+            })
+            .collect();
+        let Some(new_samples) = new_samples else {
+            return false;
+        };
+
+        let sample_scale = 1.0 / (self.samples_taken + samples) as f64;
+        let range = first_line * width..last_line * width;
+        for (index, new_sum) in range.zip(new_samples.into_iter().flatten()) {
+            self.accumulation_buffer[index] += new_sum;
+            let average = self.output_pixel(self.accumulation_buffer[index] * sample_scale);
+            let pixel = self.display_pixel(average);
+            self.color_buffer.pixels[index] = pixel;
         }
+        true
     }
 
+    // returns the sums of the samples taken for every subpixel, scaled so that dividing
+    // by the number of samples per pixel gives the average subpixel color
     fn render_pixel_samples(
         samples_per_pixel: usize,
         x: f64,
         y: f64,
-        sample_scale: f64,
         camera: &Camera,
-        world: &HittableList,
-        max_depth: i32,
-    ) -> TerminalPixel {
+        world: &World,
+        depth: &DepthPolicy,
+    ) -> RenderPixel {
         let mut pixel = RenderPixel::default();
 
         let subpixels_per_pixel = SUBPIXEL_X * SUBPIXEL_Y;
-        let subpixel_size = Vec3::new(1.0 / SUBPIXEL_X as f64, 1.0 / SUBPIXEL_Y as f64, 0.0);
-        let subpixel_sample_scale = sample_scale * subpixels_per_pixel as f64;
+        let subpixel_sample_scale = subpixels_per_pixel as f64;
 
         for _ in 0..samples_per_pixel / subpixels_per_pixel {
             for subpixel_y in 0..SUBPIXEL_Y {
                 for subpixel_x in 0..SUBPIXEL_X {
-                    //let offset = Vec3::new(subpixel_x as f64, subpixel_y as f64, 0.0)
-                    //    + Renderer::sample_square() * subpixel_size;
-                    //let offset = Vec3::default();
-                    let offset =
-                        Vec3::new(subpixel_x as f64, subpixel_y as f64, 0.0) * subpixel_size;
-                    let ray = camera.get_pixel_ray(x + offset.x, y + offset.y);
+                    // a random point in the subpixel, relative to the center of the pixel
+                    let offset_x = (subpixel_x as f64 + random_double()) / SUBPIXEL_X as f64 - 0.5;
+                    let offset_y = (subpixel_y as f64 + random_double()) / SUBPIXEL_Y as f64 - 0.5;
+                    let ray = camera.get_pixel_ray(x + offset_x, y + offset_y);
 
                     let mut subpx_color = pixel.get_color(subpixel_x, subpixel_y);
-                    subpx_color += camera.ray_color(&ray, max_depth, world) * subpixel_sample_scale;
+                    subpx_color += camera.ray_color(&ray, depth, world) * subpixel_sample_scale;
                     pixel.set_color(subpixel_x, subpixel_y, subpx_color);
                 }
             }
         }
-        //let col = pixel.average_color();
-        //TerminalPixel::new(col, col, ' ')
-        pixel.to_terminal_pixel()
+        pixel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::scene_format::parse_scene;
+
+    // a white sky in every direction, so that every pixel averages to white
+    fn white_world() -> Arc<World> {
+        let scene = parse_scene("background solid color 1 1 1").unwrap();
+        Arc::new(World::new(&scene).unwrap())
+    }
+
+    #[test]
+    fn counts_that_are_not_whole_rounds_keep_the_brightness() {
+        for (samples_per_pixel, samples_per_pass) in [(4, 8), (12, 8), (12, 4), (20, 12)] {
+            let parameters = RenderParameters {
+                width: 3,
+                height: 2,
+                samples_per_pixel,
+                mode: RenderMode::Progressive { samples_per_pass },
+                depth: DepthPolicy::default(),
+            };
+            let mut renderer = Renderer::new(parameters, &CameraSettings::default(), white_world());
+            while !renderer.is_finished() {
+                renderer.render_step();
+            }
+            assert_eq!(renderer.get_samples_taken() % (SUBPIXEL_X * SUBPIXEL_Y), 0);
+            assert!(renderer.get_samples_taken() >= samples_per_pixel);
+            let image = renderer.get_hdr_image();
+            for pixel in image.pixels() {
+                assert!((pixel - Color::white()).length() < 1e-9, "{:?}", pixel);
+            }
+            let cell = renderer.get_color_buffer().get_pixel(1, 1);
+            assert_eq!(cell.bg, Color::white());
+        }
     }
 
-    fn sample_square() -> Vec3 {
-        // sampl a random square in the pixel
-        Vec3::new(random_double() - 0.5, random_double() - 0.5, 0.0)
+    // the subpixels sample their own part of the pixel, so a sphere in front of the camera
+    // ends up in the middle of the image
+    #[test]
+    fn a_sphere_in_front_of_the_camera_is_centered() {
+        let scene = parse_scene(
+            "camera look_from 0 0 5 look_at 0 0 0
+             background solid color 0 0 0
+             material glow diffuse_light emit 1 1 1
+             sphere center 0 0 0 radius 1 material glow",
+        )
+        .unwrap();
+        let parameters = RenderParameters {
+            width: 32,
+            height: 16,
+            samples_per_pixel: 64,
+            mode: RenderMode::FinalQuality,
+            depth: DepthPolicy::default(),
+        };
+        let world = Arc::new(World::new(&scene).unwrap());
+        let mut renderer = Renderer::new(parameters, &scene.camera, world);
+        while !renderer.is_finished() {
+            renderer.render_step();
+        }
+        let image = renderer.get_hdr_image();
+        assert_eq!((image.width, image.height), (64, 64));
+        let (mut total, mut sum_x, mut sum_y) = (0.0, 0.0, 0.0);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let brightness = image.get_pixel(x, y).brightness();
+                total += brightness;
+                sum_x += brightness * x as f64;
+                sum_y += brightness * y as f64;
+            }
+        }
+        assert!(total > 0.0);
+        let centroid = (sum_x / total, sum_y / total);
+        assert!((centroid.0 - 31.5).abs() < 0.1, "{:?}", centroid);
+        assert!((centroid.1 - 31.5).abs() < 0.1, "{:?}", centroid);
     }
 }
@@ -1,68 +1,619 @@
-use crate::color::Color;
+use crate::aabb::Aabb;
+use crate::background::{Background, Environment};
+use crate::bvh::{BvhNode, BvhStats};
+use crate::camera::CameraSettings;
+use crate::color::{Color, WorkingSpace};
 use crate::geometry::*;
-use crate::materials::{Dielectric, Lambertian, Metal};
-use crate::maths::{Interval, Point};
-use std::rc::Rc;
+use crate::image::HdrImage;
+use crate::materials::{Dielectric, DiffuseLight, Isotropic, Lambertian, Material, Metal};
+use crate::maths::{degrees_to_radians, Interval, Mat4, Point, Quat, Vec3};
+use crate::medium::{ConstantMedium, Fog};
+use crate::mesh::Mesh;
+use crate::scene_format::SceneError;
+use crate::texture::{
+    CheckerTexture, ImageTexture, NoisePattern, NoiseTexture, SolidColor, Texture,
+};
+use crate::transform::{TransformParts, Transformed};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// A scene as described in a scene file: the camera, the background and fog, the working
+// space, the named textures and materials and the objects that reference them. The world
+// that is actually rendered is built from it with `build_world`, which creates each texture
+// and material once and shares it between the materials and objects that use it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub camera: CameraSettings,
+    pub background: Background,
+    pub fog: Option<Fog>,
+    pub working_space: WorkingSpace,
+    // relative paths of meshes, images and environments are relative to this directory,
+    // which is the directory of the scene file for loaded scenes
+    pub directory: PathBuf,
+    pub textures: Vec<(String, TextureDescription)>,
+    pub materials: Vec<(String, MaterialDescription)>,
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureDescription {
+    Solid {
+        color: Color,
+    },
+    // a 3D checker pattern of cubes with an edge length of `scale`
+    Checker {
+        scale: f64,
+        even: Color,
+        odd: Color,
+    },
+    // a PPM or PNG file
+    Image {
+        path: PathBuf,
+    },
+    // a procedural pattern from Perlin noise, the same seed always gives the same pattern
+    Noise {
+        pattern: NoisePattern,
+        seed: u64,
+        scale: f64,
+        octaves: usize,
+        low: Color,
+        high: Color,
+    },
+}
+
+// the color of a material, either constant or a texture referenced by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Albedo {
+    Color(Color),
+    Texture(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MaterialDescription {
+    Lambertian { albedo: Albedo },
+    Metal { albedo: Albedo, fuzz: f64 },
+    Isotropic { albedo: Albedo },
+    Dielectric { refraction_index: f64 },
+    DiffuseLight { emit: Color },
+}
+
+// Places an object in the scene. It is scaled first, then rotated around the axis by the
+// angle in degrees and then moved by the translation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransformDescription {
+    pub scale: Vec3,
+    pub rotate_axis: Vec3,
+    pub rotate_degrees: f64,
+    pub translate: Vec3,
+}
+
+impl TransformDescription {
+    pub fn parts(&self) -> TransformParts {
+        TransformParts {
+            scale: self.scale,
+            rotation: Quat::from_axis_angle(
+                self.rotate_axis,
+                degrees_to_radians(self.rotate_degrees),
+            ),
+            translation: self.translate,
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        self.parts().matrix()
+    }
+}
+
+// objects reference their material by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum ObjectDescription {
+    // a sphere that moves from `center` at time 0 to `center_end` at time 1 if given
+    Sphere {
+        center: Point,
+        center_end: Option<Point>,
+        radius: f64,
+        material: String,
+    },
+    // an infinite plane through a point
+    Plane {
+        point: Point,
+        normal: Vec3,
+        material: String,
+    },
+    // a parallelogram with a corner and two edges
+    Quad {
+        corner: Point,
+        u: Vec3,
+        v: Vec3,
+        material: String,
+    },
+    Disk {
+        center: Point,
+        normal: Vec3,
+        radius: f64,
+        material: String,
+    },
+    // an axis-aligned box between two opposite corners
+    Box {
+        min: Point,
+        max: Point,
+        material: String,
+    },
+    // an object that is scaled, rotated and moved, see TransformDescription, and animated
+    // from `transform` at time 0 to `transform_end` at time 1 if given
+    Transformed {
+        object: Box<ObjectDescription>,
+        transform: TransformDescription,
+        transform_end: Option<TransformDescription>,
+    },
+    // a volume of the density inside the closed boundary object, which scatters light with
+    // the material
+    Volume {
+        boundary: Box<ObjectDescription>,
+        density: f64,
+        material: String,
+    },
+    // a triangle mesh from an OBJ file, its faces use the materials assigned in the file
+    // and `material` where they have none
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+    },
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            camera: CameraSettings::default(),
+            background: Background::default(),
+            fog: None,
+            working_space: WorkingSpace::default(),
+            directory: PathBuf::new(),
+            textures: Vec::new(),
+            materials: Vec::new(),
+            objects: Vec::new(),
+        }
+    }
+
+    // reads a scene file, see scene_format.rs for the syntax
+    // relative paths in the file are relative to the directory of the scene file
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let mut scene = crate::scene_format::parse_scene(&text)?;
+        scene.directory = path.parent().unwrap_or(Path::new("")).to_path_buf();
+        Ok(scene)
+    }
+
+    // writes the scene in the same format that `load` reads, with the paths as they were
+    // written, so it loads the same from the same directory
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SceneError> {
+        std::fs::write(path, crate::scene_format::format_scene(self))?;
+        Ok(())
+    }
+
+    pub fn texture(&self, name: &str) -> Option<&TextureDescription> {
+        self.textures
+            .iter()
+            .find(|(texture_name, _)| texture_name == name)
+            .map(|(_, texture)| texture)
+    }
+
+    pub fn material(&self, name: &str) -> Option<&MaterialDescription> {
+        self.materials
+            .iter()
+            .find(|(material_name, _)| material_name == name)
+            .map(|(_, material)| material)
+    }
+
+    // creates the hittable objects of the scene, objects with an unknown material are skipped
+    // fails if a mesh or image file cannot be loaded
+    pub fn build_world(&self) -> Result<HittableList, SceneError> {
+        self.build_world_and_lights().map(|(world, _)| world)
+    }
+
+    // the objects of the scene and, separately, the lights among them that are sampled
+    // directly: spheres, quads and disks with a light material. The lights are part of the
+    // objects as well. All colors are converted to the working space.
+    pub fn build_world_and_lights(&self) -> Result<(HittableList, HittableList), SceneError> {
+        let space = self.working_space;
+        let textures = self
+            .textures
+            .iter()
+            .map(|(name, description)| {
+                Ok((name.as_str(), description.build(space, &self.directory)?))
+            })
+            .collect::<Result<HashMap<&str, Arc<dyn Texture>>, SceneError>>()?;
+        let materials: HashMap<&str, Arc<dyn Material>> = self
+            .materials
+            .iter()
+            .map(|(name, description)| (name.as_str(), description.build(&textures, space)))
+            .collect();
+
+        let mut builder = ObjectBuilder {
+            materials,
+            meshes: HashMap::new(),
+            working_space: space,
+            directory: &self.directory,
+        };
+        let mut world = HittableList::new();
+        let mut lights = HittableList::new();
+        for object in &self.objects {
+            let Some(built) = builder.build(object)? else {
+                continue;
+            };
+            if self.is_sampled_light(object) {
+                let light: Arc<dyn Hittable> = Arc::from(built);
+                lights.add(Box::new(Arc::clone(&light)));
+                world.add(Box::new(light));
+            } else {
+                world.add(built);
+            }
+        }
+        Ok((world, lights))
+    }
+
+    fn is_sampled_light(&self, object: &ObjectDescription) -> bool {
+        match object {
+            ObjectDescription::Sphere { material, .. }
+            | ObjectDescription::Quad { material, .. }
+            | ObjectDescription::Disk { material, .. } => matches!(
+                self.material(material),
+                Some(MaterialDescription::DiffuseLight { .. })
+            ),
+            _ => false,
+        }
+    }
+}
+
+// creates the hittable objects, meshes that are used several times are only loaded once
+// and shared between their instances
+struct ObjectBuilder<'a> {
+    materials: HashMap<&'a str, Arc<dyn Material>>,
+    meshes: HashMap<(&'a Path, Option<&'a str>), Arc<Mesh>>,
+    working_space: WorkingSpace,
+    directory: &'a Path,
+}
+
+impl<'a> ObjectBuilder<'a> {
+    // returns None for objects with an unknown material
+    fn build(
+        &mut self,
+        object: &'a ObjectDescription,
+    ) -> Result<Option<Box<dyn Hittable>>, SceneError> {
+        let material = |name: &String| self.materials.get(name.as_str()).map(Arc::clone);
+        Ok(match object {
+            ObjectDescription::Sphere {
+                center,
+                center_end,
+                radius,
+                material: name,
+            } => material(name).map(|material| {
+                let center_end = center_end.unwrap_or(*center);
+                Box::new(Sphere::moving(*center, center_end, *radius, material)) as _
+            }),
+            ObjectDescription::Plane {
+                point,
+                normal,
+                material: name,
+            } => {
+                material(name).map(|material| Box::new(Plane::new(*point, *normal, material)) as _)
+            }
+            ObjectDescription::Quad {
+                corner,
+                u,
+                v,
+                material: name,
+            } => material(name).map(|material| Box::new(Quad::new(*corner, *u, *v, material)) as _),
+            ObjectDescription::Disk {
+                center,
+                normal,
+                radius,
+                material: name,
+            } => material(name)
+                .map(|material| Box::new(Disk::new(*center, *normal, *radius, material)) as _),
+            ObjectDescription::Box {
+                min,
+                max,
+                material: name,
+            } => material(name).map(|material| Box::new(Cuboid::new(*min, *max, material)) as _),
+            ObjectDescription::Mesh { path, material } => {
+                let key = (path.as_path(), material.as_deref());
+                let mesh = match self.meshes.get(&key) {
+                    Some(mesh) => Arc::clone(mesh),
+                    None => {
+                        let fallback = material
+                            .as_deref()
+                            .and_then(|material| self.materials.get(material))
+                            .cloned();
+                        let mesh = Arc::new(Mesh::load(
+                            &self.directory.join(path),
+                            &self.materials,
+                            fallback,
+                            self.working_space,
+                        )?);
+                        self.meshes.insert(key, Arc::clone(&mesh));
+                        mesh
+                    }
+                };
+                Some(Box::new(mesh))
+            }
+            ObjectDescription::Volume {
+                boundary,
+                density,
+                material: name,
+            } => {
+                let phase_function = material(name);
+                self.build(boundary)?
+                    .zip(phase_function)
+                    .map(|(boundary, phase_function)| {
+                        let boundary: Arc<dyn Hittable> = Arc::from(boundary);
+                        Box::new(ConstantMedium::new(boundary, *density, phase_function)) as _
+                    })
+            }
+            ObjectDescription::Transformed {
+                object,
+                transform,
+                transform_end,
+            } => self.build(object)?.and_then(|object| {
+                let object: Arc<dyn Hittable> = Arc::from(object);
+                let transformed = match transform_end {
+                    Some(end) => Transformed::animated(object, transform.parts(), end.parts()),
+                    None => Transformed::new(object, transform.matrix()),
+                };
+                transformed.map(|transformed| Box::new(transformed) as _)
+            }),
+        })
+    }
+}
+
+impl Default for Scene {
+    fn default() -> Self {
+        let mut scene = Self::new();
+        scene.materials = vec![
+            (
+                "ground".to_string(),
+                MaterialDescription::Lambertian {
+                    albedo: Albedo::Color(Color::new(0.8, 0.8, 0.0)),
+                },
+            ),
+            (
+                "center".to_string(),
+                MaterialDescription::Lambertian {
+                    albedo: Albedo::Color(Color::new(0.1, 0.2, 0.5)),
+                },
+            ),
+            (
+                "glass".to_string(),
+                MaterialDescription::Dielectric {
+                    refraction_index: 1.50,
+                },
+            ),
+            (
+                "bubble".to_string(),
+                MaterialDescription::Dielectric {
+                    refraction_index: 1.0 / 1.5,
+                },
+            ),
+            (
+                "gold".to_string(),
+                MaterialDescription::Metal {
+                    albedo: Albedo::Color(Color::new(0.8, 0.6, 0.2)),
+                    fuzz: 1.0,
+                },
+            ),
+        ];
+
+        let sphere = |center: Point, radius: f64, material: &str| ObjectDescription::Sphere {
+            center,
+            center_end: None,
+            radius,
+            material: material.to_string(),
+        };
+        scene.objects = vec![
+            ObjectDescription::Plane {
+                point: Point::new(0.0, -0.5, 0.0),
+                normal: Vec3::new(0.0, 1.0, 0.0),
+                material: "ground".to_string(),
+            },
+            sphere(Point::new(0.0, 0.0, -1.2), 0.5, "center"),
+            sphere(Point::new(-1.0, 0.0, -1.0), 0.5, "glass"),
+            sphere(Point::new(-1.0, 0.0, -1.0), 0.4, "bubble"),
+            sphere(Point::new(1.0, 0.0, -1.0), 0.5, "gold"),
+        ];
+        scene
+    }
+}
+
+impl TextureDescription {
+    // fails if an image cannot be loaded, relative image paths are relative to `directory`
+    pub fn build(
+        &self,
+        space: WorkingSpace,
+        directory: &Path,
+    ) -> Result<Arc<dyn Texture>, SceneError> {
+        let color = |color: &Color| space.convert_srgb(*color);
+        Ok(match self {
+            TextureDescription::Solid { color: solid } => Arc::new(SolidColor::new(color(solid))),
+            TextureDescription::Checker { scale, even, odd } => {
+                Arc::new(CheckerTexture::from_colors(*scale, color(even), color(odd)))
+            }
+            TextureDescription::Image { path } => {
+                let path = directory.join(path);
+                let image = HdrImage::load(&path).map_err(|error| SceneError::InFile {
+                    path,
+                    error: Box::new(error.into()),
+                })?;
+                Arc::new(ImageTexture::new(
+                    image.map(|pixel| space.convert_srgb(pixel)),
+                ))
+            }
+            TextureDescription::Noise {
+                pattern,
+                seed,
+                scale,
+                octaves,
+                low,
+                high,
+            } => Arc::new(NoiseTexture::new(
+                *pattern,
+                *seed,
+                *scale,
+                *octaves,
+                color(low),
+                color(high),
+            )),
+        })
+    }
+}
+
+impl Albedo {
+    // unknown textures show up in pink
+    fn build(
+        &self,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+        space: WorkingSpace,
+    ) -> Arc<dyn Texture> {
+        match self {
+            Albedo::Color(color) => Arc::new(SolidColor::new(space.convert_srgb(*color))),
+            Albedo::Texture(name) => textures
+                .get(name.as_str())
+                .map(Arc::clone)
+                .unwrap_or_else(|| Arc::new(SolidColor::new(Color::pink()))),
+        }
+    }
+}
+
+impl MaterialDescription {
+    pub fn build(
+        &self,
+        textures: &HashMap<&str, Arc<dyn Texture>>,
+        space: WorkingSpace,
+    ) -> Arc<dyn Material> {
+        match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Arc::new(Lambertian::textured(albedo.build(textures, space)))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => {
+                Arc::new(Metal::textured(albedo.build(textures, space), *fuzz))
+            }
+            MaterialDescription::Isotropic { albedo } => {
+                Arc::new(Isotropic::textured(albedo.build(textures, space)))
+            }
+            MaterialDescription::Dielectric { refraction_index } => {
+                Arc::new(Dielectric::new(*refraction_index))
+            }
+            MaterialDescription::DiffuseLight { emit } => {
+                Arc::new(DiffuseLight::new(space.convert_srgb(*emit)))
+            }
+        }
+    }
+}
+
+// The scene as it is rendered: the objects of a scene inside a bounding volume hierarchy,
+// the background that rays see when they leave the scene and the fog in between. The lights
+// that can be sampled directly are also kept in a list of their own. Its colors are in the
+// working space of the scene.
+// It is built once and shared between the render threads and the UI.
+pub struct World {
+    pub objects: Arc<dyn Hittable>,
+    pub background: Arc<dyn Environment>,
+    pub fog: Option<Fog>,
+    pub lights: Arc<HittableList>,
+    pub working_space: WorkingSpace,
+    pub object_count: usize,
+    pub bvh_stats: BvhStats,
+}
+
+impl World {
+    pub fn new(scene: &Scene) -> Result<Self, SceneError> {
+        let (list, lights) = scene.build_world_and_lights()?;
+        let object_count = list.object_count();
+        // infinite objects like planes have no useful bounding box, they are tested
+        // separately next to the hierarchy of all the others
+        let (bounded, mut objects) = list.split_unbounded();
+        let bvh_stats = match BvhNode::with_stats(bounded) {
+            Some((bvh, stats)) => {
+                objects.add(Box::new(bvh));
+                stats
+            }
+            None => BvhStats::default(),
+        };
+        Ok(Self {
+            objects: Arc::new(objects),
+            background: scene
+                .background
+                .build(scene.working_space, &scene.directory)?,
+            fog: scene.fog.map(|fog| Fog {
+                color: scene.working_space.convert_srgb(fog.color),
+                ..fog
+            }),
+            lights: Arc::new(lights),
+            working_space: scene.working_space,
+            object_count,
+            bvh_stats,
+        })
+    }
+
+    // the same objects under a different sky, without building the hierarchy again
+    pub fn with_background(&self, background: Arc<dyn Environment>) -> Self {
+        Self {
+            objects: Arc::clone(&self.objects),
+            background,
+            fog: self.fog,
+            lights: Arc::clone(&self.lights),
+            working_space: self.working_space,
+            object_count: self.object_count,
+            bvh_stats: self.bvh_stats,
+        }
+    }
+}
 
 pub struct HittableList {
     objects: Vec<Box<dyn Hittable>>,
+    bbox: Aabb,
 }
 
 impl HittableList {
     pub fn new() -> Self {
         Self {
             objects: Vec::new(),
+            bbox: Aabb::empty(),
         }
     }
 
-    pub fn default() -> Self {
-        let mut world = Self::new();
-
-        let material_ground = Rc::new(Lambertian::new(Color::new(0.8, 0.8, 0.0)));
-        let material_center = Rc::new(Lambertian::new(Color::new(0.1, 0.2, 0.5)));
-        let material_left = Rc::new(Dielectric::new(1.50));
-        let material_bubble = Rc::new(Dielectric::new(1.0 / 1.5));
-        let material_right = Rc::new(Metal::new(Color::new(0.8, 0.6, 0.2), 1.0));
-
-        world.add(Box::new(Sphere::new(
-            Point::new(0.0, -100.5, -1.0),
-            100.0,
-            material_ground,
-        )));
-        world.add(Box::new(Sphere::new(
-            Point::new(0.0, 0.0, -1.2),
-            0.5,
-            material_center,
-        )));
-        world.add(Box::new(Sphere::new(
-            Point::new(-1.0, 0.0, -1.0),
-            0.5,
-            material_left,
-        )));
-        world.add(Box::new(Sphere::new(
-            Point::new(-1.0, 0.0, -1.0),
-            0.4,
-            material_bubble,
-        )));
-        world.add(Box::new(Sphere::new(
-            Point::new(1.0, 0.0, -1.0),
-            0.5,
-            material_right,
-        )));
-
-        world
-    }
-
     pub fn add(&mut self, object: Box<dyn Hittable>) {
+        self.bbox = Aabb::surrounding(&self.bbox, &object.bounding_box());
         self.objects.push(object);
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.bbox = Aabb::empty();
+    }
+
+    // hands out the objects, e.g. to build an acceleration structure from them
+    pub fn into_objects(self) -> Vec<Box<dyn Hittable>> {
+        self.objects
     }
     pub fn object_count(&self) -> usize {
         self.objects.len()
     }
+
+    // separates the objects with a finite bounding box from the unbounded ones
+    pub fn split_unbounded(self) -> (HittableList, HittableList) {
+        let mut bounded = HittableList::new();
+        let mut unbounded = HittableList::new();
+        for object in self.objects {
+            if object.bounding_box().is_bounded() {
+                bounded.add(object);
+            } else {
+                unbounded.add(object);
+            }
+        }
+        (bounded, unbounded)
+    }
 }
 
 impl Hittable for HittableList {
@@ -75,4 +626,29 @@ impl Hittable for HittableList {
                 _ => Some(record),
             })
     }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+
+    // one of the objects is picked at random, so the densities are averaged
+    fn pdf_value(&self, ray: &Ray) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(ray))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: Point, time: f64) -> Vec3 {
+        if self.objects.is_empty() {
+            return Vec3::new(1.0, 0.0, 0.0);
+        }
+        let index = (crate::random::random_double() * self.objects.len() as f64) as usize;
+        self.objects[index.min(self.objects.len() - 1)].random(origin, time)
+    }
 }
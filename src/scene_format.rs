@@ -0,0 +1,1584 @@
+//! Text format for scenes.
+//!
+//! A scene file is a list of statements, one per line. Everything after a `#` is a comment.
+//! Each statement starts with a keyword, followed by `property value...` pairs in any order:
+//!
+//! ```text
+//! camera look_from 0 0 0 look_at 0 0 -1 vup 0 1 0 vfov 90 defocus_angle 0 focus_distance 1
+//! background gradient bottom 1 1 1 top 0.5 0.7 1   # or e.g. `background solid color 0 0 0`
+//! fog density 0.1 color 0.8 0.8 0.9
+//!
+//! texture checker checker scale 0.5 even 0.2 0.3 0.1 odd 0.9 0.9 0.9
+//! texture earth image file earthmap.png   # or `texture name solid color r g b`
+//! texture stone marble seed 7 scale 4 octaves 7 low 0.1 0.1 0.1 high 1 1 1
+//!
+//! material ground lambertian texture checker
+//! material gold metal albedo 0.8 0.6 0.2 fuzz 1
+//! material glass dielectric refraction_index 1.5
+//! material lamp diffuse_light emit 4 4 4
+//! material smoke isotropic albedo 0.2 0.2 0.2
+//!
+//! plane point 0 -0.5 0 normal 0 1 0 material ground
+//! sphere center 0 0 -1 radius 0.5 material glass
+//! quad corner -1 0 -2 u 2 0 0 v 0 1 0 material gold
+//! disk center 0 1 -1 normal 0 -1 0 radius 0.3 material ground
+//! box min -0.2 -0.5 -0.2 max 0.2 0 0.2 material gold
+//! mesh file models/teapot.obj material gold
+//! sphere center 0 2 -3 radius 1 material smoke density 0.5
+//! ```
+//!
+//! Textures and materials have a name and have to be defined before they are used.
+//! Lambertian and metal materials take either a constant `albedo` color or a `texture`.
+//! Spheres, quads and disks with a `diffuse_light` material are sampled directly as lights,
+//! which is much less noisy than waiting for rays to find lights of other shapes.
+//! Image textures are PPM, PNG, PFM or Radiance .hdr files. The noise textures `marble`,
+//! `wood` and `clouds` blend between the colors `low` and `high` and are reproducible from
+//! their `seed`.
+//! Every object can be placed with `scale x y z`, `rotate axis_x axis_y axis_z degrees`
+//! and `translate x y z`, which are applied in this order, e.g. to turn a box:
+//!
+//! ```text
+//! box min 0 0 0 max 1 2 1 material gold rotate 0 1 0 15 translate 2 0 -1
+//! ```
+//!
+//! The optional `fog` fills the whole scene, surfaces fade into its color with their
+//! distance. Any closed object with a `density` is the boundary of a volume like smoke, which
+//! scatters light with the object's material anywhere inside. Use an `isotropic` material,
+//! which scatters into all directions alike.
+//!
+//! An `environment` background surrounds the scene with an equirectangular HDR image in
+//! Radiance .hdr, PFM or any other image format, with the center of the image towards -z:
+//!
+//! ```text
+//! background environment file sky.hdr rotate 90 intensity 1.5
+//! ```
+//!
+//! `rotate` turns it around the y axis by degrees and `intensity` scales its brightness. Its
+//! bright parts are sampled directly as lights, so that e.g. a small sun converges quickly.
+//! A `sky` background is a physically based daylight sky with a sun, see sky.rs:
+//!
+//! ```text
+//! background sky elevation 30 azimuth 45 turbidity 3 intensity 1
+//! ```
+//!
+//! The sun stands `elevation` degrees above the horizon, in [0, 90], and is turned `azimuth`
+//! degrees from -z towards +x. `turbidity` from 1.7 to 10 goes from clear to hazy air.
+//!
+//! Motion blur samples every ray at a time between the camera's `shutter_open` and
+//! `shutter_close`, both in [0, 1] and 0 by default. A sphere with a `center_end` moves from
+//! `center` at time 0 to `center_end` at time 1, and an object with any of `scale_end`,
+//! `rotate_end` or `translate_end` is animated from its transformation at time 0 to that one
+//! at time 1. Rotations turn the shorter way, so a half turn per frame is the most.
+//!
+//! Colors in scene files, images and MTL files are linear sRGB, but light can bounce around
+//! in a wider gamut, where saturated colors mix more like they do in reality:
+//!
+//! ```text
+//! working_space rec2020   # or the default `working_space srgb`
+//! ```
+//!
+//! Meshes are read from OBJ files, see mesh.rs, and a file that is used several times is
+//! only loaded once. Their `material` is optional and only used for faces that have no
+//! material of their own.
+
+use crate::background::Background;
+use crate::camera::CameraSettings;
+use crate::color::{Color, WorkingSpace};
+use crate::maths::Vec3;
+use crate::medium::Fog;
+use crate::scene::{
+    Albedo, MaterialDescription, ObjectDescription, Scene, TextureDescription, TransformDescription,
+};
+use crate::texture::NoisePattern;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    // line and column are 1-based
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    // an error in another file that the scene refers to, e.g. a mesh
+    InFile {
+        path: PathBuf,
+        error: Box<SceneError>,
+    },
+}
+
+impl std::fmt::Display for SceneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "{}", error),
+            SceneError::Parse {
+                line,
+                column,
+                message,
+            } => write!(f, "line {}, column {}: {}", line, column, message),
+            SceneError::InFile { path, error } => write!(f, "{}: {}", path.display(), error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+// a word of the scene file and where it was found
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Token<'a> {
+    pub text: &'a str,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl<'a> Token<'a> {
+    pub fn error(&self, message: impl Into<String>) -> SceneError {
+        SceneError::Parse {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+
+    pub fn number(&self) -> Result<f64, SceneError> {
+        self.text
+            .parse::<f64>()
+            .map_err(|_| self.error(format!("expected a number, found `{}`", self.text)))
+    }
+}
+
+// splits a line into tokens, dropping comments
+pub(crate) fn tokenize(line: &str, line_number: usize) -> Vec<Token<'_>> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut tokens = Vec::new();
+    let mut start = None;
+    // columns count characters, not bytes
+    for (column, (index, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((index, column)),
+            (true, Some((start_index, start_column))) => {
+                tokens.push(Token {
+                    text: &line[start_index..index],
+                    line: line_number,
+                    column: start_column + 1,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((start_index, start_column)) = start {
+        tokens.push(Token {
+            text: &line[start_index..],
+            line: line_number,
+            column: start_column + 1,
+        });
+    }
+    tokens
+}
+
+// the `property value...` pairs of a statement
+struct Properties<'a> {
+    statement: Token<'a>,
+    values: Vec<(Token<'a>, Vec<Token<'a>>)>,
+}
+
+impl<'a> Properties<'a> {
+    // parses the tokens according to the schema, which lists the allowed properties
+    // and the number of values each of them takes
+    fn parse(
+        statement: Token<'a>,
+        tokens: &[Token<'a>],
+        schema: &[(&str, usize)],
+    ) -> Result<Self, SceneError> {
+        let mut values: Vec<(Token<'a>, Vec<Token<'a>>)> = Vec::new();
+        let mut remaining = tokens;
+        while let Some((name, rest)) = remaining.split_first() {
+            let Some(&(_, count)) = schema.iter().find(|(property, _)| *property == name.text)
+            else {
+                let allowed: Vec<&str> = schema.iter().map(|(property, _)| *property).collect();
+                return Err(name.error(format!(
+                    "unknown property `{}` for `{}`, expected one of: {}",
+                    name.text,
+                    statement.text,
+                    allowed.join(", ")
+                )));
+            };
+            if values
+                .iter()
+                .any(|(existing, _)| existing.text == name.text)
+            {
+                return Err(name.error(format!("property `{}` is set twice", name.text)));
+            }
+            if rest.len() < count {
+                let position = rest.last().unwrap_or(name);
+                return Err(position.error(format!(
+                    "property `{}` takes {} value(s), found {}",
+                    name.text,
+                    count,
+                    rest.len()
+                )));
+            }
+            values.push((*name, rest[..count].to_vec()));
+            remaining = &rest[count..];
+        }
+        Ok(Self { statement, values })
+    }
+
+    fn get(&self, name: &str) -> Option<&[Token<'a>]> {
+        self.values
+            .iter()
+            .find(|(property, _)| property.text == name)
+            .map(|(_, values)| values.as_slice())
+    }
+
+    fn require(&self, name: &str) -> Result<&[Token<'a>], SceneError> {
+        self.get(name).ok_or_else(|| {
+            self.statement.error(format!(
+                "`{}` is missing the property `{}`",
+                self.statement.text, name
+            ))
+        })
+    }
+
+    fn number(&self, name: &str) -> Result<f64, SceneError> {
+        self.require(name)?[0].number()
+    }
+
+    fn number_or(&self, name: &str, default: f64) -> Result<f64, SceneError> {
+        self.get(name)
+            .map_or(Ok(default), |values| values[0].number())
+    }
+
+    // a whole number that is not negative
+    fn count_or(&self, name: &str, default: usize) -> Result<usize, SceneError> {
+        let Some(values) = self.get(name) else {
+            return Ok(default);
+        };
+        values[0].text.parse().map_err(|_| {
+            values[0].error(format!(
+                "expected a whole number for `{}`, found `{}`",
+                name, values[0].text
+            ))
+        })
+    }
+
+    fn vec3(&self, name: &str) -> Result<Vec3, SceneError> {
+        let values = self.require(name)?;
+        Ok(Vec3::new(
+            values[0].number()?,
+            values[1].number()?,
+            values[2].number()?,
+        ))
+    }
+
+    fn optional_vec3(&self, name: &str) -> Result<Option<Vec3>, SceneError> {
+        self.get(name).map(|_| self.vec3(name)).transpose()
+    }
+
+    fn vec3_or(&self, name: &str, default: Vec3) -> Result<Vec3, SceneError> {
+        match self.get(name) {
+            Some(_) => self.vec3(name),
+            None => Ok(default),
+        }
+    }
+
+    // a vector that is used as a direction and therefore must not be zero
+    fn direction(&self, name: &str) -> Result<Vec3, SceneError> {
+        let direction = self.vec3(name)?;
+        if direction.near_zero() {
+            return Err(self.require(name)?[0].error(format!("`{}` must not be zero", name)));
+        }
+        Ok(direction)
+    }
+
+    fn word(&self, name: &str) -> Result<Token<'a>, SceneError> {
+        Ok(self.require(name)?[0])
+    }
+}
+
+// the `material` property of an object, which has to name a material defined before
+fn object_material(scene: &Scene, properties: &Properties) -> Result<String, SceneError> {
+    let material = properties.word("material")?;
+    if scene.material(material.text).is_none() {
+        return Err(material.error(format!("unknown material `{}`", material.text)));
+    }
+    Ok(material.text.to_string())
+}
+
+pub fn parse_scene(text: &str) -> Result<Scene, SceneError> {
+    let mut scene = Scene::new();
+    let mut has_camera = false;
+    let mut has_background = false;
+    let mut has_fog = false;
+    let mut has_working_space = false;
+
+    for (line_index, line) in text.lines().enumerate() {
+        let tokens = tokenize(line, line_index + 1);
+        let Some((keyword, arguments)) = tokens.split_first() else {
+            continue;
+        };
+
+        match keyword.text {
+            "camera" => {
+                if has_camera {
+                    return Err(keyword.error("the camera is defined twice"));
+                }
+                has_camera = true;
+                scene.camera = parse_camera(*keyword, arguments)?;
+            }
+            "background" => {
+                if has_background {
+                    return Err(keyword.error("the background is defined twice"));
+                }
+                has_background = true;
+                scene.background = parse_background(*keyword, arguments)?;
+            }
+            "fog" => {
+                if has_fog {
+                    return Err(keyword.error("the fog is defined twice"));
+                }
+                has_fog = true;
+                scene.fog = Some(parse_fog(*keyword, arguments)?);
+            }
+            "working_space" => {
+                if has_working_space {
+                    return Err(keyword.error("the working space is defined twice"));
+                }
+                has_working_space = true;
+                scene.working_space = parse_working_space(*keyword, arguments)?;
+            }
+            "texture" => {
+                let (name, texture) = parse_texture(*keyword, arguments)?;
+                if scene.texture(name.text).is_some() {
+                    return Err(name.error(format!("texture `{}` is defined twice", name.text)));
+                }
+                scene.textures.push((name.text.to_string(), texture));
+            }
+            "material" => {
+                let (name, material) = parse_material(&scene, *keyword, arguments)?;
+                if scene.material(name.text).is_some() {
+                    return Err(name.error(format!("material `{}` is defined twice", name.text)));
+                }
+                scene.materials.push((name.text.to_string(), material));
+            }
+            "sphere" | "plane" | "quad" | "disk" | "box" | "mesh" => {
+                let object = parse_object(&scene, *keyword, arguments)?;
+                scene.objects.push(object);
+            }
+            _ => {
+                return Err(keyword.error(format!(
+                    "unknown statement `{}`, expected camera, background, fog, working_space, \
+                     texture, material, sphere, plane, quad, disk, box or mesh",
+                    keyword.text
+                )))
+            }
+        }
+    }
+    Ok(scene)
+}
+
+// the properties that every object takes to place it with a transformation
+const TRANSFORM_SCHEMA: [(&str, usize); 6] = [
+    ("scale", 3),
+    ("rotate", 4),
+    ("translate", 3),
+    ("scale_end", 3),
+    ("rotate_end", 4),
+    ("translate_end", 3),
+];
+
+// the schema of an object with the transform properties and `density`, which fills it
+fn object_schema<'s>(schema: &[(&'s str, usize)]) -> Vec<(&'s str, usize)> {
+    [schema, &TRANSFORM_SCHEMA, &[("density", 1)]].concat()
+}
+
+fn parse_object<'a>(
+    scene: &Scene,
+    keyword: Token<'a>,
+    arguments: &[Token<'a>],
+) -> Result<ObjectDescription, SceneError> {
+    let (object, properties) = match keyword.text {
+        "sphere" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[
+                    ("center", 3),
+                    ("center_end", 3),
+                    ("radius", 1),
+                    ("material", 1),
+                ]),
+            )?;
+            let object = ObjectDescription::Sphere {
+                center: properties.vec3("center")?,
+                center_end: properties.optional_vec3("center_end")?,
+                radius: properties.number("radius")?,
+                material: object_material(scene, &properties)?,
+            };
+            (object, properties)
+        }
+        "plane" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[("point", 3), ("normal", 3), ("material", 1)]),
+            )?;
+            let object = ObjectDescription::Plane {
+                point: properties.vec3("point")?,
+                normal: properties.direction("normal")?,
+                material: object_material(scene, &properties)?,
+            };
+            (object, properties)
+        }
+        "quad" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[("corner", 3), ("u", 3), ("v", 3), ("material", 1)]),
+            )?;
+            let u = properties.vec3("u")?;
+            let v = properties.vec3("v")?;
+            if u.cross(v).near_zero() {
+                return Err(keyword.error("the edges u and v of a quad must not be parallel"));
+            }
+            let object = ObjectDescription::Quad {
+                corner: properties.vec3("corner")?,
+                u,
+                v,
+                material: object_material(scene, &properties)?,
+            };
+            (object, properties)
+        }
+        "disk" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[("center", 3), ("normal", 3), ("radius", 1), ("material", 1)]),
+            )?;
+            let object = ObjectDescription::Disk {
+                center: properties.vec3("center")?,
+                normal: properties.direction("normal")?,
+                radius: properties.number("radius")?,
+                material: object_material(scene, &properties)?,
+            };
+            (object, properties)
+        }
+        "box" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[("min", 3), ("max", 3), ("material", 1)]),
+            )?;
+            let object = ObjectDescription::Box {
+                min: properties.vec3("min")?,
+                max: properties.vec3("max")?,
+                material: object_material(scene, &properties)?,
+            };
+            (object, properties)
+        }
+        "mesh" => {
+            let properties = Properties::parse(
+                keyword,
+                arguments,
+                &object_schema(&[("file", 1), ("material", 1)]),
+            )?;
+            let material = properties.get("material").map(|values| values[0]);
+            if let Some(material) = material {
+                if scene.material(material.text).is_none() {
+                    return Err(material.error(format!("unknown material `{}`", material.text)));
+                }
+            }
+            let object = ObjectDescription::Mesh {
+                path: properties.word("file")?.text.into(),
+                material: material.map(|material| material.text.to_string()),
+            };
+            (object, properties)
+        }
+        _ => unreachable!("not an object statement"),
+    };
+    let object = parse_transform(object, &properties)?;
+    parse_volume(scene, object, &properties)
+}
+
+// turns the object into the boundary of a volume if it has a `density`, the volume scatters
+// light with the object's material, which is usually isotropic
+fn parse_volume(
+    scene: &Scene,
+    object: ObjectDescription,
+    properties: &Properties,
+) -> Result<ObjectDescription, SceneError> {
+    let Some(values) = properties.get("density") else {
+        return Ok(object);
+    };
+    let density = values[0].number()?;
+    if density <= 0.0 {
+        return Err(values[0].error("density must be positive"));
+    }
+    Ok(ObjectDescription::Volume {
+        boundary: Box::new(object),
+        density,
+        material: object_material(scene, properties)?,
+    })
+}
+
+// wraps the object in a transformation if any of the transform properties is given, and
+// animates it if any of the `_end` properties is given
+fn parse_transform(
+    object: ObjectDescription,
+    properties: &Properties,
+) -> Result<ObjectDescription, SceneError> {
+    let given =
+        |names: &[(&str, usize)]| names.iter().any(|(name, _)| properties.get(name).is_some());
+    if !given(&TRANSFORM_SCHEMA) {
+        return Ok(object);
+    }
+
+    let identity = TransformDescription {
+        scale: Vec3::one(),
+        rotate_axis: Vec3::new(0.0, 1.0, 0.0),
+        rotate_degrees: 0.0,
+        translate: Vec3::zero(),
+    };
+    let transform =
+        parse_transform_properties(properties, ["scale", "rotate", "translate"], identity)?;
+    // the end of the animation defaults to the start for everything that is not animated
+    let transform_end = if given(&TRANSFORM_SCHEMA[3..]) {
+        let names = ["scale_end", "rotate_end", "translate_end"];
+        let end = parse_transform_properties(properties, names, transform)?;
+        let flips = |start: f64, end: f64| start * end < 0.0;
+        if flips(transform.scale.x, end.scale.x)
+            || flips(transform.scale.y, end.scale.y)
+            || flips(transform.scale.z, end.scale.z)
+        {
+            return Err(properties.require("scale_end")?[0]
+                .error("scale_end must have the same signs as scale"));
+        }
+        Some(end)
+    } else {
+        None
+    };
+    Ok(ObjectDescription::Transformed {
+        object: Box::new(object),
+        transform,
+        transform_end,
+    })
+}
+
+// reads the scale, rotate and translate properties with the given names
+fn parse_transform_properties(
+    properties: &Properties,
+    [scale_name, rotate_name, translate_name]: [&str; 3],
+    defaults: TransformDescription,
+) -> Result<TransformDescription, SceneError> {
+    let scale = properties.vec3_or(scale_name, defaults.scale)?;
+    if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+        return Err(
+            properties.require(scale_name)?[0].error(format!("{} must not be zero", scale_name))
+        );
+    }
+    let (rotate_axis, rotate_degrees) = match properties.get(rotate_name) {
+        Some(values) => {
+            let axis = Vec3::new(
+                values[0].number()?,
+                values[1].number()?,
+                values[2].number()?,
+            );
+            if axis.near_zero() {
+                return Err(values[0].error("the rotation axis must not be zero"));
+            }
+            (axis, values[3].number()?)
+        }
+        None => (defaults.rotate_axis, defaults.rotate_degrees),
+    };
+    Ok(TransformDescription {
+        scale,
+        rotate_axis,
+        rotate_degrees,
+        translate: properties.vec3_or(translate_name, defaults.translate)?,
+    })
+}
+
+fn parse_camera(keyword: Token, arguments: &[Token]) -> Result<CameraSettings, SceneError> {
+    let defaults = CameraSettings::default();
+    let properties = Properties::parse(
+        keyword,
+        arguments,
+        &[
+            ("look_from", 3),
+            ("look_at", 3),
+            ("vup", 3),
+            ("vfov", 1),
+            ("defocus_angle", 1),
+            ("focus_distance", 1),
+            ("shutter_open", 1),
+            ("shutter_close", 1),
+        ],
+    )?;
+    let look_from = properties.vec3_or("look_from", defaults.look_from)?;
+    let look_at = properties.vec3_or("look_at", defaults.look_at)?;
+    let camera = CameraSettings {
+        look_from,
+        look_at,
+        vup: properties.vec3_or("vup", defaults.vup)?,
+        vfov: properties.number_or("vfov", defaults.vfov)?,
+        defocus_angle: properties.number_or("defocus_angle", defaults.defocus_angle)?,
+        // focus on the look_at point unless told otherwise
+        focus_distance: properties.number_or("focus_distance", (look_from - look_at).length())?,
+        shutter_open: properties.number_or("shutter_open", defaults.shutter_open)?,
+        shutter_close: properties.number_or("shutter_close", defaults.shutter_close)?,
+    };
+    // a degenerate camera would produce NaN rays
+    if (camera.look_from - camera.look_at).near_zero() {
+        return Err(keyword.error("look_from and look_at must be different points"));
+    }
+    if camera
+        .vup
+        .cross(camera.look_from - camera.look_at)
+        .near_zero()
+    {
+        return Err(keyword.error("vup must not be parallel to the view direction"));
+    }
+    if camera.vfov <= 0.0 || camera.vfov >= 180.0 {
+        return Err(keyword.error("vfov must be between 0 and 180 degrees"));
+    }
+    if camera.focus_distance <= 0.0 {
+        return Err(keyword.error("focus_distance must be positive"));
+    }
+    if !(0.0 <= camera.shutter_open
+        && camera.shutter_open <= camera.shutter_close
+        && camera.shutter_close <= 1.0)
+    {
+        return Err(keyword.error("the shutter must open and close between 0 and 1, in order"));
+    }
+    Ok(camera)
+}
+
+fn parse_texture<'a>(
+    keyword: Token<'a>,
+    arguments: &[Token<'a>],
+) -> Result<(Token<'a>, TextureDescription), SceneError> {
+    let [name, kind, arguments @ ..] = arguments else {
+        let position = arguments.last().unwrap_or(&keyword);
+        return Err(position.error("expected `texture <name> <type> ...`"));
+    };
+
+    let texture = match kind.text {
+        "solid" => {
+            let properties = Properties::parse(*kind, arguments, &[("color", 3)])?;
+            TextureDescription::Solid {
+                color: properties.vec3("color")?,
+            }
+        }
+        "checker" => {
+            let properties =
+                Properties::parse(*kind, arguments, &[("scale", 1), ("even", 3), ("odd", 3)])?;
+            let scale = properties.number_or("scale", 1.0)?;
+            if scale <= 0.0 {
+                return Err(properties.word("scale")?.error("scale must be positive"));
+            }
+            TextureDescription::Checker {
+                scale,
+                even: properties.vec3("even")?,
+                odd: properties.vec3("odd")?,
+            }
+        }
+        "image" => {
+            let properties = Properties::parse(*kind, arguments, &[("file", 1)])?;
+            TextureDescription::Image {
+                path: properties.word("file")?.text.into(),
+            }
+        }
+        "marble" | "wood" | "clouds" => {
+            let pattern = match kind.text {
+                "marble" => NoisePattern::Marble,
+                "wood" => NoisePattern::Wood,
+                _ => NoisePattern::Clouds,
+            };
+            let properties = Properties::parse(
+                *kind,
+                arguments,
+                &[
+                    ("seed", 1),
+                    ("scale", 1),
+                    ("octaves", 1),
+                    ("low", 3),
+                    ("high", 3),
+                ],
+            )?;
+            let scale = properties.number_or("scale", 1.0)?;
+            if scale <= 0.0 {
+                return Err(properties.word("scale")?.error("scale must be positive"));
+            }
+            TextureDescription::Noise {
+                pattern,
+                seed: properties.count_or("seed", 0)? as u64,
+                scale,
+                octaves: properties.count_or("octaves", 7)?,
+                low: properties.vec3_or("low", Color::black())?,
+                high: properties.vec3_or("high", Color::white())?,
+            }
+        }
+        _ => {
+            return Err(kind.error(format!(
+                "unknown texture type `{}`, expected solid, checker, image, marble, wood \
+                 or clouds",
+                kind.text
+            )))
+        }
+    };
+    Ok((*name, texture))
+}
+
+// the color of a material, given by exactly one of `albedo` and `texture`
+fn parse_albedo(scene: &Scene, properties: &Properties) -> Result<Albedo, SceneError> {
+    match (properties.get("albedo"), properties.get("texture")) {
+        (Some(_), Some(texture)) => {
+            Err(texture[0].error("a material takes either an albedo or a texture, not both"))
+        }
+        (Some(_), None) => Ok(Albedo::Color(properties.vec3("albedo")?)),
+        (None, Some(texture)) => {
+            let texture = texture[0];
+            if scene.texture(texture.text).is_none() {
+                return Err(texture.error(format!("unknown texture `{}`", texture.text)));
+            }
+            Ok(Albedo::Texture(texture.text.to_string()))
+        }
+        (None, None) => Err(properties.statement.error(format!(
+            "`{}` needs an albedo or a texture",
+            properties.statement.text
+        ))),
+    }
+}
+
+fn parse_material<'a>(
+    scene: &Scene,
+    keyword: Token<'a>,
+    arguments: &[Token<'a>],
+) -> Result<(Token<'a>, MaterialDescription), SceneError> {
+    let [name, kind, arguments @ ..] = arguments else {
+        let position = arguments.last().unwrap_or(&keyword);
+        return Err(position.error("expected `material <name> <type> ...`"));
+    };
+
+    let material = match kind.text {
+        "lambertian" => {
+            let properties = Properties::parse(*kind, arguments, &[("albedo", 3), ("texture", 1)])?;
+            MaterialDescription::Lambertian {
+                albedo: parse_albedo(scene, &properties)?,
+            }
+        }
+        "metal" => {
+            let properties = Properties::parse(
+                *kind,
+                arguments,
+                &[("albedo", 3), ("texture", 1), ("fuzz", 1)],
+            )?;
+            MaterialDescription::Metal {
+                albedo: parse_albedo(scene, &properties)?,
+                fuzz: properties.number_or("fuzz", 0.0)?,
+            }
+        }
+        "dielectric" => {
+            let properties = Properties::parse(*kind, arguments, &[("refraction_index", 1)])?;
+            MaterialDescription::Dielectric {
+                refraction_index: properties.number("refraction_index")?,
+            }
+        }
+        "isotropic" => {
+            let properties = Properties::parse(*kind, arguments, &[("albedo", 3), ("texture", 1)])?;
+            MaterialDescription::Isotropic {
+                albedo: parse_albedo(scene, &properties)?,
+            }
+        }
+        "diffuse_light" => {
+            let properties = Properties::parse(*kind, arguments, &[("emit", 3)])?;
+            MaterialDescription::DiffuseLight {
+                emit: properties.vec3("emit")?,
+            }
+        }
+        _ => {
+            return Err(kind.error(format!(
+                "unknown material type `{}`, expected lambertian, metal, dielectric, \
+                 isotropic or diffuse_light",
+                kind.text
+            )))
+        }
+    };
+    Ok((*name, material))
+}
+
+fn parse_fog(keyword: Token, arguments: &[Token]) -> Result<Fog, SceneError> {
+    let properties = Properties::parse(keyword, arguments, &[("density", 1), ("color", 3)])?;
+    let density = properties.number("density")?;
+    if density < 0.0 {
+        return Err(properties
+            .word("density")?
+            .error("density must not be negative"));
+    }
+    Ok(Fog {
+        density,
+        color: properties.vec3("color")?,
+    })
+}
+
+fn parse_working_space(keyword: Token, arguments: &[Token]) -> Result<WorkingSpace, SceneError> {
+    let [name] = arguments else {
+        return Err(keyword.error("expected `working_space srgb` or `working_space rec2020`"));
+    };
+    WorkingSpace::from_name(name.text).ok_or_else(|| {
+        name.error(format!(
+            "unknown working space `{}`, expected srgb or rec2020",
+            name.text
+        ))
+    })
+}
+
+fn parse_background(keyword: Token, arguments: &[Token]) -> Result<Background, SceneError> {
+    let [kind, arguments @ ..] = arguments else {
+        return Err(keyword.error("expected `background <type> ...`"));
+    };
+    match kind.text {
+        "solid" => {
+            let properties = Properties::parse(*kind, arguments, &[("color", 3)])?;
+            Ok(Background::Solid {
+                color: properties.vec3("color")?,
+            })
+        }
+        "gradient" => {
+            let properties = Properties::parse(*kind, arguments, &[("bottom", 3), ("top", 3)])?;
+            Ok(Background::Gradient {
+                bottom: properties.vec3("bottom")?,
+                top: properties.vec3("top")?,
+            })
+        }
+        "environment" => {
+            let properties = Properties::parse(
+                *kind,
+                arguments,
+                &[("file", 1), ("rotate", 1), ("intensity", 1)],
+            )?;
+            let intensity = properties.number_or("intensity", 1.0)?;
+            if intensity < 0.0 {
+                let token = properties.require("intensity")?[0];
+                return Err(token.error("`intensity` must not be negative"));
+            }
+            Ok(Background::Environment {
+                path: properties.word("file")?.text.into(),
+                rotation: properties.number_or("rotate", 0.0)?,
+                intensity,
+            })
+        }
+        "sky" => {
+            let properties = Properties::parse(
+                *kind,
+                arguments,
+                &[
+                    ("elevation", 1),
+                    ("azimuth", 1),
+                    ("turbidity", 1),
+                    ("intensity", 1),
+                ],
+            )?;
+            let elevation = properties.number_or("elevation", 45.0)?;
+            if !(0.0..=90.0).contains(&elevation) {
+                let token = properties.require("elevation")?[0];
+                return Err(token.error("`elevation` must be between 0 and 90 degrees"));
+            }
+            let turbidity = properties.number_or("turbidity", 3.0)?;
+            if !(1.7..=10.0).contains(&turbidity) {
+                let token = properties.require("turbidity")?[0];
+                return Err(token.error("`turbidity` must be between 1.7 and 10"));
+            }
+            let intensity = properties.number_or("intensity", 1.0)?;
+            if intensity < 0.0 {
+                let token = properties.require("intensity")?[0];
+                return Err(token.error("`intensity` must not be negative"));
+            }
+            Ok(Background::Sky {
+                elevation,
+                azimuth: properties.number_or("azimuth", 0.0)?,
+                turbidity,
+                intensity,
+            })
+        }
+        _ => Err(kind.error(format!(
+            "unknown background type `{}`, expected solid, gradient, environment or sky",
+            kind.text
+        ))),
+    }
+}
+
+// writes the scene so that `parse_scene` reads it back unchanged
+pub fn format_scene(scene: &Scene) -> String {
+    // f64 display prints the shortest representation that parses back to the same value
+    fn vec3(v: &Vec3) -> String {
+        format!("{} {} {}", v.x, v.y, v.z)
+    }
+    fn color(c: &Color) -> String {
+        vec3(c)
+    }
+    fn albedo(albedo: &Albedo) -> String {
+        match albedo {
+            Albedo::Color(c) => format!("albedo {}", color(c)),
+            Albedo::Texture(name) => format!("texture {}", name),
+        }
+    }
+
+    let mut text = String::new();
+    let camera = &scene.camera;
+    let _ = writeln!(
+        text,
+        "camera look_from {} look_at {} vup {} vfov {} defocus_angle {} focus_distance {} \
+         shutter_open {} shutter_close {}",
+        vec3(&camera.look_from),
+        vec3(&camera.look_at),
+        vec3(&camera.vup),
+        camera.vfov,
+        camera.defocus_angle,
+        camera.focus_distance,
+        camera.shutter_open,
+        camera.shutter_close
+    );
+    let _ = match &scene.background {
+        Background::Solid { color: solid } => {
+            writeln!(text, "background solid color {}", color(solid))
+        }
+        Background::Gradient { bottom, top } => writeln!(
+            text,
+            "background gradient bottom {} top {}",
+            color(bottom),
+            color(top)
+        ),
+        Background::Environment {
+            path,
+            rotation,
+            intensity,
+        } => writeln!(
+            text,
+            "background environment file {} rotate {} intensity {}",
+            path.display(),
+            rotation,
+            intensity
+        ),
+        Background::Sky {
+            elevation,
+            azimuth,
+            turbidity,
+            intensity,
+        } => writeln!(
+            text,
+            "background sky elevation {} azimuth {} turbidity {} intensity {}",
+            elevation, azimuth, turbidity, intensity
+        ),
+    };
+    if let Some(fog) = &scene.fog {
+        let _ = writeln!(
+            text,
+            "fog density {} color {}",
+            fog.density,
+            color(&fog.color)
+        );
+    }
+    if scene.working_space != WorkingSpace::default() {
+        let _ = writeln!(text, "working_space {}", scene.working_space.name());
+    }
+
+    if !scene.textures.is_empty() {
+        text.push('\n');
+    }
+    for (name, texture) in &scene.textures {
+        let _ = match texture {
+            TextureDescription::Solid { color: solid } => {
+                writeln!(text, "texture {} solid color {}", name, color(solid))
+            }
+            TextureDescription::Checker { scale, even, odd } => writeln!(
+                text,
+                "texture {} checker scale {} even {} odd {}",
+                name,
+                scale,
+                color(even),
+                color(odd)
+            ),
+            TextureDescription::Image { path } => {
+                writeln!(text, "texture {} image file {}", name, path.display())
+            }
+            TextureDescription::Noise {
+                pattern,
+                seed,
+                scale,
+                octaves,
+                low,
+                high,
+            } => {
+                let pattern = match pattern {
+                    NoisePattern::Marble => "marble",
+                    NoisePattern::Wood => "wood",
+                    NoisePattern::Clouds => "clouds",
+                };
+                writeln!(
+                    text,
+                    "texture {} {} seed {} scale {} octaves {} low {} high {}",
+                    name,
+                    pattern,
+                    seed,
+                    scale,
+                    octaves,
+                    color(low),
+                    color(high)
+                )
+            }
+        };
+    }
+
+    if !scene.materials.is_empty() {
+        text.push('\n');
+    }
+    for (name, material) in &scene.materials {
+        let _ = match material {
+            MaterialDescription::Lambertian {
+                albedo: material_albedo,
+            } => {
+                writeln!(
+                    text,
+                    "material {} lambertian {}",
+                    name,
+                    albedo(material_albedo)
+                )
+            }
+            MaterialDescription::Isotropic {
+                albedo: material_albedo,
+            } => writeln!(
+                text,
+                "material {} isotropic {}",
+                name,
+                albedo(material_albedo)
+            ),
+            MaterialDescription::Metal {
+                albedo: material_albedo,
+                fuzz,
+            } => writeln!(
+                text,
+                "material {} metal {} fuzz {}",
+                name,
+                albedo(material_albedo),
+                fuzz
+            ),
+            MaterialDescription::Dielectric { refraction_index } => writeln!(
+                text,
+                "material {} dielectric refraction_index {}",
+                name, refraction_index
+            ),
+            MaterialDescription::DiffuseLight { emit } => {
+                writeln!(text, "material {} diffuse_light emit {}", name, color(emit))
+            }
+        };
+    }
+
+    // writes the statement of an object without the line break
+    fn write_transform(
+        text: &mut String,
+        transform: &TransformDescription,
+        suffix: &str,
+    ) -> std::fmt::Result {
+        write!(
+            text,
+            " scale{suffix} {} rotate{suffix} {} {} translate{suffix} {}",
+            vec3(&transform.scale),
+            vec3(&transform.rotate_axis),
+            transform.rotate_degrees,
+            vec3(&transform.translate)
+        )
+    }
+
+    fn format_object(text: &mut String, object: &ObjectDescription) {
+        let _ = match object {
+            ObjectDescription::Sphere {
+                center,
+                center_end,
+                radius,
+                material,
+            } => {
+                let _ = write!(text, "sphere center {}", vec3(center));
+                if let Some(center_end) = center_end {
+                    let _ = write!(text, " center_end {}", vec3(center_end));
+                }
+                write!(text, " radius {} material {}", radius, material)
+            }
+            ObjectDescription::Plane {
+                point,
+                normal,
+                material,
+            } => write!(
+                text,
+                "plane point {} normal {} material {}",
+                vec3(point),
+                vec3(normal),
+                material
+            ),
+            ObjectDescription::Quad {
+                corner,
+                u,
+                v,
+                material,
+            } => write!(
+                text,
+                "quad corner {} u {} v {} material {}",
+                vec3(corner),
+                vec3(u),
+                vec3(v),
+                material
+            ),
+            ObjectDescription::Disk {
+                center,
+                normal,
+                radius,
+                material,
+            } => write!(
+                text,
+                "disk center {} normal {} radius {} material {}",
+                vec3(center),
+                vec3(normal),
+                radius,
+                material
+            ),
+            ObjectDescription::Box { min, max, material } => write!(
+                text,
+                "box min {} max {} material {}",
+                vec3(min),
+                vec3(max),
+                material
+            ),
+            ObjectDescription::Mesh { path, material } => {
+                let _ = write!(text, "mesh file {}", path.display());
+                match material {
+                    Some(material) => write!(text, " material {}", material),
+                    None => Ok(()),
+                }
+            }
+            ObjectDescription::Volume {
+                boundary, density, ..
+            } => {
+                format_object(text, boundary);
+                write!(text, " density {}", density)
+            }
+            ObjectDescription::Transformed {
+                object,
+                transform,
+                transform_end,
+            } => {
+                format_object(text, object);
+                let _ = write_transform(text, transform, "");
+                match transform_end {
+                    Some(end) => write_transform(text, end, "_end"),
+                    None => Ok(()),
+                }
+            }
+        };
+    }
+
+    if !scene.objects.is_empty() {
+        text.push('\n');
+    }
+    for object in &scene.objects {
+        format_object(&mut text, object);
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::World;
+    use std::path::Path;
+
+    fn parse_error(text: &str) -> (usize, usize, String) {
+        match parse_scene(text) {
+            Err(SceneError::Parse {
+                line,
+                column,
+                message,
+            }) => (line, column, message),
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn default_scene_round_trips() {
+        let scene = Scene::default();
+        let text = format_scene(&scene);
+        assert_eq!(parse_scene(&text).unwrap(), scene);
+    }
+
+    #[test]
+    fn demo_scene_file_matches_default_scene() {
+        let scene = Scene::load(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/demo.txt")).unwrap();
+        assert_eq!(
+            scene.directory,
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes")
+        );
+        assert_eq!(
+            Scene {
+                directory: PathBuf::new(),
+                ..scene
+            },
+            Scene::default()
+        );
+    }
+
+    #[test]
+    fn parses_statements_with_comments() {
+        let text = "
+            # a small scene
+            camera vfov 40 look_from 0 1 2
+            material red lambertian albedo 1 0 0 # trailing comment
+            sphere radius 0.5 material red center 0 0 -1
+        ";
+        let scene = parse_scene(text).unwrap();
+        assert_eq!(scene.camera.look_from, Vec3::new(0.0, 1.0, 2.0));
+        assert_eq!(scene.camera.look_at, CameraSettings::default().look_at);
+        assert_eq!(scene.camera.vfov, 40.0);
+        assert_eq!(scene.materials.len(), 1);
+        assert_eq!(
+            scene.objects,
+            vec![ObjectDescription::Sphere {
+                center: Vec3::new(0.0, 0.0, -1.0),
+                center_end: None,
+                radius: 0.5,
+                material: "red".to_string(),
+            }]
+        );
+        assert_eq!(scene.build_world().unwrap().object_count(), 1);
+    }
+
+    #[test]
+    fn parses_lights_and_backgrounds() {
+        let text = "
+            background solid color 0 0 0
+            material lamp diffuse_light emit 4 4 4
+            quad corner 0 1 0 u 1 0 0 v 0 0 1 material lamp
+        ";
+        let scene = parse_scene(text).unwrap();
+        assert_eq!(
+            scene.background,
+            Background::Solid {
+                color: Color::black()
+            }
+        );
+        assert_eq!(
+            scene.material("lamp"),
+            Some(&MaterialDescription::DiffuseLight {
+                emit: Color::new(4.0, 4.0, 4.0)
+            })
+        );
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+
+        let (line, column, _) = parse_error("background sunset");
+        assert_eq!((line, column), (1, 12));
+
+        let scene = parse_scene("background environment file sky.hdr rotate 90").unwrap();
+        assert_eq!(
+            scene.background,
+            Background::Environment {
+                path: "sky.hdr".into(),
+                rotation: 90.0,
+                intensity: 1.0,
+            }
+        );
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+        let (line, column, _) = parse_error("background environment file a.hdr intensity -1");
+        assert_eq!((line, column), (1, 45));
+
+        let scene = parse_scene("background sky elevation 10 turbidity 2").unwrap();
+        assert_eq!(
+            scene.background,
+            Background::Sky {
+                elevation: 10.0,
+                azimuth: 0.0,
+                turbidity: 2.0,
+                intensity: 1.0,
+            }
+        );
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+        let (line, column, _) = parse_error("background sky elevation 95");
+        assert_eq!((line, column), (1, 26));
+    }
+
+    #[test]
+    fn loads_textures_relative_to_the_scene_file() {
+        let directory = std::env::temp_dir().join(format!("rats-texture-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("scene.txt"),
+            "texture tiles checker scale 0.5 even 0 0 0 odd 1 1 1\n\
+             texture photo image file photo.ppm\n\
+             texture veins marble seed 42 scale 4 low 0.2 0.2 0.3\n\
+             material floor lambertian texture tiles\n\
+             material framed metal texture photo fuzz 0.1\n\
+             sphere center 0 0 -1 radius 0.5 material framed\n",
+        )
+        .unwrap();
+        std::fs::write(directory.join("photo.ppm"), "P3 1 1 255 255 0 0\n").unwrap();
+
+        let scene = Scene::load(directory.join("scene.txt")).unwrap();
+        assert_eq!(
+            scene.material("floor"),
+            Some(&MaterialDescription::Lambertian {
+                albedo: Albedo::Texture("tiles".to_string())
+            })
+        );
+        scene.save(directory.join("saved.txt")).unwrap();
+        assert_eq!(Scene::load(directory.join("saved.txt")).unwrap(), scene);
+        assert_eq!(scene.build_world().unwrap().object_count(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn cornell_box_scene_file_parses() {
+        let scene =
+            Scene::load(concat!(env!("CARGO_MANIFEST_DIR"), "/scenes/cornell.txt")).unwrap();
+        assert_eq!(scene.objects.len(), 8);
+    }
+
+    #[test]
+    fn procedural_scene_file_builds() {
+        let scene = Scene::load(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/scenes/procedural.txt"
+        ))
+        .unwrap();
+        assert_eq!(scene.textures.len(), 3);
+        assert_eq!(scene.build_world().unwrap().object_count(), 4);
+    }
+
+    #[test]
+    fn parses_transformed_objects() {
+        let text = "
+            material red lambertian albedo 1 0 0
+            box min 0 0 0 max 1 1 1 material red rotate 0 1 0 15 translate 2 0 -1
+            sphere center 0 0 0 radius 1 material red scale 1 2 1
+        ";
+        let scene = parse_scene(text).unwrap();
+        let ObjectDescription::Transformed {
+            object,
+            transform,
+            transform_end: None,
+        } = &scene.objects[0]
+        else {
+            panic!("expected a transformed box, got {:?}", scene.objects[0]);
+        };
+        assert!(matches!(**object, ObjectDescription::Box { .. }));
+        assert_eq!(
+            *transform,
+            TransformDescription {
+                scale: Vec3::one(),
+                rotate_axis: Vec3::new(0.0, 1.0, 0.0),
+                rotate_degrees: 15.0,
+                translate: Vec3::new(2.0, 0.0, -1.0),
+            }
+        );
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+        assert_eq!(scene.build_world().unwrap().object_count(), 2);
+
+        let (line, column, message) = parse_error(
+            "material red lambertian albedo 1 0 0\n\
+             box min 0 0 0 max 1 1 1 material red scale 1 0 1",
+        );
+        assert_eq!((line, column), (2, 44));
+        assert!(message.contains("scale"));
+    }
+
+    #[test]
+    fn parses_fog_and_volumes() {
+        let text = "
+            fog density 0.1 color 0.8 0.8 0.9
+            material smoke isotropic albedo 0.2 0.2 0.2
+            box min 0 0 0 max 1 1 1 material smoke translate 0 1 0 density 0.5
+            sphere center 0 0 -1 radius 0.5 material smoke
+        ";
+        let scene = parse_scene(text).unwrap();
+        assert_eq!(
+            scene.fog,
+            Some(Fog {
+                density: 0.1,
+                color: Color::new(0.8, 0.8, 0.9)
+            })
+        );
+        let ObjectDescription::Volume {
+            boundary,
+            density,
+            material,
+        } = &scene.objects[0]
+        else {
+            panic!("expected a volume, got {:?}", scene.objects[0]);
+        };
+        // the volume holds the whole transformed box
+        assert!(matches!(**boundary, ObjectDescription::Transformed { .. }));
+        assert_eq!((*density, material.as_str()), (0.5, "smoke"));
+        assert!(matches!(scene.objects[1], ObjectDescription::Sphere { .. }));
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+        assert_eq!(scene.build_world().unwrap().object_count(), 2);
+
+        let (line, column, _) = parse_error(
+            "material smoke isotropic albedo 0.2 0.2 0.2\n\
+             sphere center 0 0 0 radius 1 material smoke density -1",
+        );
+        assert_eq!((line, column), (2, 53));
+        let (line, column, _) = parse_error("fog color 1 1 1");
+        assert_eq!((line, column), (1, 1));
+    }
+
+    #[test]
+    fn parses_the_working_space() {
+        assert_eq!(parse_scene("").unwrap().working_space, WorkingSpace::Srgb);
+        let scene = parse_scene("working_space rec2020").unwrap();
+        assert_eq!(scene.working_space, WorkingSpace::Rec2020);
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+
+        let (line, column, _) = parse_error("working_space aces");
+        assert_eq!((line, column), (1, 15));
+        let (line, column, _) = parse_error("working_space srgb\nworking_space srgb");
+        assert_eq!((line, column), (2, 1));
+    }
+
+    #[test]
+    fn parses_motion_blur() {
+        let text = "
+            camera shutter_open 0.25 shutter_close 1
+            material red lambertian albedo 1 0 0
+            sphere center 0 0 -1 center_end 0 0.5 -1 radius 0.5 material red
+            box min 0 0 0 max 1 1 1 material red translate 2 0 -1 rotate_end 0 1 0 90
+        ";
+        let scene = parse_scene(text).unwrap();
+        assert_eq!(
+            (scene.camera.shutter_open, scene.camera.shutter_close),
+            (0.25, 1.0)
+        );
+        assert!(matches!(
+            scene.objects[0],
+            ObjectDescription::Sphere {
+                center_end: Some(end),
+                ..
+            } if end == Vec3::new(0.0, 0.5, -1.0)
+        ));
+        // everything that is not animated stays as it is at the start
+        let ObjectDescription::Transformed {
+            transform,
+            transform_end: Some(end),
+            ..
+        } = &scene.objects[1]
+        else {
+            panic!("expected an animated box, got {:?}", scene.objects[1]);
+        };
+        assert_eq!(end.translate, transform.translate);
+        assert_eq!(end.scale, transform.scale);
+        assert_eq!(end.rotate_degrees, 90.0);
+        assert_eq!(parse_scene(&format_scene(&scene)).unwrap(), scene);
+        assert_eq!(scene.build_world().unwrap().object_count(), 2);
+
+        let (line, column, _) = parse_error("camera shutter_open 0.5 shutter_close 0.25");
+        assert_eq!((line, column), (1, 1));
+        let (line, column, message) = parse_error(
+            "material red lambertian albedo 1 0 0\n\
+             box min 0 0 0 max 1 1 1 material red scale 1 1 1 scale_end 1 -1 1",
+        );
+        assert_eq!((line, column), (2, 60));
+        assert!(message.contains("scale_end"));
+    }
+
+    #[test]
+    fn loads_meshes_relative_to_the_scene_file() {
+        let directory = std::env::temp_dir().join(format!("rats-mesh-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("scene.txt"),
+            "material red lambertian albedo 1 0 0\n\
+             mesh file triangle.obj material red\n\
+             mesh file triangle.obj material red translate 0 0 -1\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("triangle.obj"),
+            "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load(directory.join("scene.txt")).unwrap();
+        scene.save(directory.join("saved.txt")).unwrap();
+        assert_eq!(Scene::load(directory.join("saved.txt")).unwrap(), scene);
+        assert_eq!(scene.build_world().unwrap().object_count(), 2);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    // the paths in a scene stay as they are written, so that saving a scene that was loaded
+    // from a relative path and loading it again gives the same scene
+    #[test]
+    fn relative_scene_paths_survive_saving() {
+        // tests run in the package directory
+        let directory = PathBuf::from(format!("target/rats-relative-{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("models")).unwrap();
+        std::fs::write(
+            directory.join("scene.txt"),
+            "background environment file sky.ppm\n\
+             texture photo image file photo.ppm\n\
+             material framed lambertian texture photo\n\
+             mesh file models/triangle.obj material framed\n",
+        )
+        .unwrap();
+        std::fs::write(directory.join("photo.ppm"), "P3 1 1 255 255 0 0\n").unwrap();
+        std::fs::write(
+            directory.join("sky.ppm"),
+            "P3 2 1 255 0 0 255 255 255 255\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("models/triangle.obj"),
+            "v 0 0 -1\nv 1 0 -1\nv 0 1 -1\nf 1 2 3\n",
+        )
+        .unwrap();
+
+        let scene = Scene::load(directory.join("scene.txt")).unwrap();
+        assert_eq!(
+            scene.texture("photo"),
+            Some(&TextureDescription::Image {
+                path: "photo.ppm".into()
+            })
+        );
+        scene.save(directory.join("scene.txt")).unwrap();
+        let reloaded = Scene::load(directory.join("scene.txt")).unwrap();
+        assert_eq!(reloaded, scene);
+        assert!(World::new(&reloaded).is_ok());
+        assert_eq!(reloaded.build_world().unwrap().object_count(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reports_line_and_column_of_errors() {
+        let (line, column, _) = parse_error("material red lambertian albedo 1 x 0");
+        assert_eq!((line, column), (1, 34));
+
+        let (line, column, message) = parse_error("material red lambertian texture wood");
+        assert_eq!((line, column), (1, 33));
+        assert!(message.contains("unknown texture"));
+
+        let (line, column, message) = parse_error("\n\nsphere center 0 0 0 radius 1 material blue");
+        assert_eq!((line, column), (3, 39));
+        assert!(message.contains("unknown material"));
+
+        let (line, column, message) = parse_error("camera\n  sphere center 0 0 0");
+        assert_eq!((line, column), (2, 3));
+        assert!(message.contains("missing"));
+
+        let (line, column, _) = parse_error("light position 0 0 0");
+        assert_eq!((line, column), (1, 1));
+    }
+}
@@ -0,0 +1,212 @@
+//! A procedural daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model
+//! for Daylight" (1999), with a sun disk that is sampled like a light.
+
+use crate::background::Environment;
+use crate::color::{Color, WorkingSpace};
+use crate::maths::*;
+use crate::random::random_double;
+use palette::convert::FromColorUnclamped;
+use palette::white_point::D65;
+use palette::{Xyz, Yxy};
+use std::f64::consts::PI;
+
+// the sun is about half a degree wide
+const SUN_ANGULAR_RADIUS: f64 = 0.265 * PI / 180.0;
+// the luminance of the sun outside of the atmosphere in kcd/m²
+const SUN_LUMINANCE: f64 = 2.0e6;
+// the model works in kcd/m², which is scaled so that white in full sun comes out about one
+const LUMINANCE_SCALE: f64 = 0.03;
+
+// The clear sky for a sun position, which is brightest around the sun and towards the
+// horizon. `turbidity` is the haziness of the air, from 2 for a very clear day to about 10.
+// Below the horizon, the sky continues with its color at the horizon.
+pub struct Sky {
+    sun_direction: Vec3,
+    sun_color: Color,
+    cos_sun_radius: f64,
+    // luminance Y and chromaticity x, y: at the zenith, and the Perez coefficients A to E
+    zenith: [f64; 3],
+    perez: [[f64; 5]; 3],
+    sun_zenith_angle: f64,
+    intensity: f64,
+    space: WorkingSpace,
+}
+
+impl Sky {
+    // the sun is `elevation` degrees above the horizon and turned `azimuth` degrees from -z
+    // towards +x. The model only holds while the sun is up, so the elevation is clamped to
+    // [0, 90]. Its colors are computed in the working space `space`.
+    pub fn new(
+        elevation: f64,
+        azimuth: f64,
+        turbidity: f64,
+        intensity: f64,
+        space: WorkingSpace,
+    ) -> Self {
+        let elevation = degrees_to_radians(elevation.clamp(0.0, 90.0));
+        let azimuth = degrees_to_radians(azimuth);
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        let theta = PI / 2.0 - elevation;
+        let t = turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let cubic = |c: [f64; 4]| c[0] * theta.powi(3) + c[1] * theta.powi(2) + c[2] * theta + c[3];
+        let zenith_x = t * t * cubic([0.00166, -0.00375, 0.00209, 0.0])
+            + t * cubic([-0.02903, 0.06377, -0.03202, 0.00394])
+            + cubic([0.11693, -0.21196, 0.06052, 0.25886]);
+        let zenith_y = t * t * cubic([0.00275, -0.00610, 0.00317, 0.0])
+            + t * cubic([-0.04214, 0.08970, -0.04153, 0.00516])
+            + cubic([0.15346, -0.26756, 0.06670, 0.26688]);
+
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        Self {
+            sun_direction,
+            sun_color: space.convert_srgb(sun_transmittance(theta, turbidity)) * SUN_LUMINANCE,
+            cos_sun_radius: SUN_ANGULAR_RADIUS.cos(),
+            zenith: [zenith_luminance, zenith_x, zenith_y],
+            perez,
+            sun_zenith_angle: theta,
+            intensity,
+            space,
+        }
+    }
+
+    // the sky without the sun, in kcd/m²
+    fn sky_color(&self, direction: Vec3) -> Color {
+        let cos_theta = direction.y.max(0.001);
+        let cos_gamma = direction.dot(self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+        let perez = |[a, b, c, d, e]: [f64; 5], cos_theta: f64, gamma: f64, cos_gamma: f64| {
+            (1.0 + a * (b / cos_theta).exp())
+                * (1.0 + c * (d * gamma).exp() + e * cos_gamma * cos_gamma)
+        };
+        let sun = self.sun_zenith_angle;
+        let [luminance, x, y] = std::array::from_fn(|i| {
+            self.zenith[i] * perez(self.perez[i], cos_theta, gamma, cos_gamma)
+                / perez(self.perez[i], 1.0, sun, sun.cos())
+        });
+        let xyz = Xyz::<D65, f64>::from_color_unclamped(Yxy::new(x, y, luminance));
+        let color = self.space.convert_xyz(xyz);
+        // the chromaticities of the model can lie a little outside of the gamut
+        Color::new(color.x.max(0.0), color.y.max(0.0), color.z.max(0.0))
+    }
+}
+
+// How much of the sun's light makes it through the air, for sRGB red, green and blue at
+// 680, 550 and 440 nm. Air scatters blue light much more than red (Rayleigh), haze scatters
+// all alike (Ångström's formula), and the lower the sun, the longer the way through the air.
+fn sun_transmittance(zenith_angle: f64, turbidity: f64) -> Color {
+    let degrees = zenith_angle.to_degrees();
+    let air_mass = 1.0 / (zenith_angle.cos() + 0.15 * (93.885 - degrees).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |wavelength: f64| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Color::new(
+        transmittance(0.68),
+        transmittance(0.55),
+        transmittance(0.44),
+    )
+}
+
+impl Environment for Sky {
+    fn color(&self, direction: Vec3) -> Color {
+        let direction = direction.normalized();
+        let mut color = self.sky_color(direction);
+        if direction.dot(self.sun_direction) >= self.cos_sun_radius {
+            color += self.sun_color;
+        }
+        color * (LUMINANCE_SCALE * self.intensity)
+    }
+
+    // only the sun is sampled, the sky is smooth enough to be found by the materials
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, direction: Vec3) -> f64 {
+        if direction.normalized().dot(self.sun_direction) >= self.cos_sun_radius {
+            1.0 / (2.0 * PI * (1.0 - self.cos_sun_radius))
+        } else {
+            0.0
+        }
+    }
+
+    // a uniformly random direction within the sun disk
+    fn random(&self) -> Vec3 {
+        let cos_theta = 1.0 - random_double() * (1.0 - self.cos_sun_radius);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * random_double();
+        let (u, v) = self.sun_direction.perpendicular_basis();
+        u * (sin_theta * phi.cos()) + v * (sin_theta * phi.sin()) + self.sun_direction * cos_theta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sky_is_blue_and_the_evening_sun_is_red() {
+        let noon = Sky::new(60.0, 0.0, 3.0, 1.0, WorkingSpace::Srgb);
+        let zenith = noon.color(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z > zenith.x);
+        // the sky is brighter towards the sun and whiter towards the horizon
+        let away = noon.color(Vec3::new(0.0, 1.0, 1.0));
+        assert!(noon.color(Vec3::new(0.0, 1.0, -0.9)).y > away.y);
+        let horizon = noon.color(Vec3::new(0.0, 0.05, 1.0));
+        assert!(horizon.z / horizon.x < zenith.z / zenith.x);
+
+        let evening = Sky::new(3.0, 90.0, 3.0, 1.0, WorkingSpace::Srgb);
+        assert!((evening.sun_direction - Vec3::new(0.9986, 0.0523, 0.0)).length() < 1e-3);
+        let noon_sun = noon.sun_color / noon.sun_color.y;
+        let evening_sun = evening.sun_color / evening.sun_color.y;
+        assert!(evening_sun.x > noon_sun.x && evening_sun.z < noon_sun.z);
+        assert!(evening.sun_color.y < noon.sun_color.y);
+    }
+
+    #[test]
+    fn sun_samples_cover_the_sun_disk() {
+        let sky = Sky::new(40.0, -30.0, 2.5, 1.0, WorkingSpace::Srgb);
+        let pdf = 1.0 / (2.0 * PI * (1.0 - sky.cos_sun_radius));
+        let samples = 1000;
+        let mean = (0..samples).fold(Vec3::zero(), |sum, _| {
+            let direction = sky.random();
+            assert_eq!(sky.pdf_value(direction), pdf);
+            assert!(sky.color(direction).y > 1000.0);
+            sum + direction
+        }) / samples as f64;
+        assert!((mean.normalized() - sky.sun_direction).length() < 5e-4);
+        assert_eq!(sky.pdf_value(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    }
+}
@@ -49,6 +49,26 @@ pub struct RenderPixel {
     colors: [[Color; SUBPIXEL_X]; SUBPIXEL_Y],
 }
 
+// adds the subpixel colors, used to accumulate samples
+impl std::ops::AddAssign for RenderPixel {
+    fn add_assign(&mut self, other: Self) {
+        for (row, other_row) in self.colors.iter_mut().zip(other.colors.iter()) {
+            for (color, other_color) in row.iter_mut().zip(other_row.iter()) {
+                *color += *other_color;
+            }
+        }
+    }
+}
+// scales all subpixel colors
+impl std::ops::Mul<f64> for RenderPixel {
+    type Output = Self;
+    fn mul(self, other: f64) -> Self {
+        Self {
+            colors: self.colors.map(|row| row.map(|color| color * other)),
+        }
+    }
+}
+
 impl RenderPixel {
     pub fn new() -> Self {
         Self {
@@ -63,6 +83,13 @@ impl RenderPixel {
         self.colors[y][x] = color;
     }
 
+    // applies the function to every subpixel color, e.g. to tone map them
+    pub fn map(self, function: impl Fn(Color) -> Color) -> Self {
+        Self {
+            colors: self.colors.map(|row| row.map(&function)),
+        }
+    }
+
     pub fn average_color(&self) -> Color {
         let mut color = Color::black();
         for row in self.colors.iter() {
@@ -73,7 +100,7 @@ impl RenderPixel {
         color / (SUBPIXEL_X * SUBPIXEL_Y) as f64
     }
 
-    pub fn to_terminal_pixel(&self) -> TerminalPixel {
+    pub fn to_terminal_pixel(self) -> TerminalPixel {
         // // average the upper and the lower half separately
         // let color_upper =
         //     (self.colors[0][0] + self.colors[0][1] + self.colors[1][0] + self.colors[1][1]) / 4.0;
@@ -82,7 +109,7 @@ impl RenderPixel {
         // return TerminalPixel::new(color_lower, color_upper, '▄');
 
         // foreground pixels are part of the first cluster, which is the "darker" one
-        let mut is_foreground_pixel: &mut [[bool; SUBPIXEL_X]; SUBPIXEL_Y] =
+        let is_foreground_pixel: &mut [[bool; SUBPIXEL_X]; SUBPIXEL_Y] =
             &mut [[false; SUBPIXEL_X]; SUBPIXEL_Y];
         let (color_fg, color_bg) =
             foreground_background_detection(&self.colors, is_foreground_pixel);
@@ -129,65 +156,74 @@ impl RenderPixel {
     }
 }
 
-// basic k-means clustering algorithm with k=2, assigning the 8 subpixels to either foreground or background
+// k-means clustering with k=2, assigning the 8 subpixels to either foreground or background.
+// The subpixels are compared in OKLab, where the same distance looks like the same
+// difference, so that dark colors are told apart as well as bright ones. The clusters start
+// from the two colors that are farthest apart, and the returned colors are the averages of
+// the linear colors in each cluster, the darker one first.
 fn foreground_background_detection(
     colors: &[[Color; SUBPIXEL_X]; SUBPIXEL_Y],
     is_foreground: &mut [[bool; SUBPIXEL_X]; SUBPIXEL_Y],
 ) -> (Color, Color) {
-    let mut cluster_centers = [Color::default(); 2];
-    // initialize cluster centers
-    cluster_centers[0] = colors[0][0];
-    // find the first color in colors that is not equal to the first cluster center
-    let mut found = false;
-    for row in colors.iter() {
-        for color in row.iter() {
-            if *color != cluster_centers[0] {
-                cluster_centers[1] = *color;
-                found = true;
-                break;
+    let labs = colors.map(|row| row.map(|color| color.saturated().to_oklab()));
+    let all_labs: Vec<Vec3> = labs.iter().flatten().copied().collect();
+
+    // seed with the farthest pair, there are only 28 pairs to try
+    let mut farthest = (0, 0, 0.0);
+    for (i, a) in all_labs.iter().enumerate() {
+        for (j, b) in all_labs.iter().enumerate().skip(i + 1) {
+            let distance = (*a - *b).length_squared();
+            if distance > farthest.2 {
+                farthest = (i, j, distance);
             }
         }
     }
-    // if no other color was found, return the same color for both clusters
-    if !found {
-        return (cluster_centers[0], cluster_centers[0]);
+    let flat_colors: Vec<Color> = colors.iter().flatten().copied().collect();
+    // if all colors look the same, return the same color for both clusters
+    if farthest.2 == 0.0 {
+        return (flat_colors[0], flat_colors[0]);
     }
+    let mut cluster_centers = [all_labs[farthest.0], all_labs[farthest.1]];
     // make sure the first cluster center is the darker one
-    if cluster_centers[0].brightness() > cluster_centers[1].brightness() {
+    if cluster_centers[0].x > cluster_centers[1].x {
         cluster_centers.swap(0, 1);
     }
 
+    let mut cluster_colors = [Color::default(); 2];
     for _ in 0..16 {
-        let mut new_cluster_centers = [Color::default(); 2];
+        let mut new_cluster_centers = [Vec3::default(); 2];
+        let mut new_cluster_colors = [Color::default(); 2];
         let mut new_cluster_counts = [0; 2];
         // assign each color to a cluster
-        for (ri, row) in colors.iter().enumerate() {
-            for (ci, col) in row.iter().enumerate() {
-                let color = *col;
-                let dist0 = (color - cluster_centers[0]).length_squared();
-                let dist1 = (color - cluster_centers[1]).length_squared();
-                if dist0 < dist1 {
-                    new_cluster_centers[0] += color;
-                    new_cluster_counts[0] += 1;
-                    is_foreground[ri][ci] = true;
-                } else {
-                    new_cluster_centers[1] += color;
-                    new_cluster_counts[1] += 1;
-                    is_foreground[ri][ci] = false;
-                }
+        for (ri, row) in labs.iter().enumerate() {
+            for (ci, lab) in row.iter().enumerate() {
+                let dist0 = (*lab - cluster_centers[0]).length_squared();
+                let dist1 = (*lab - cluster_centers[1]).length_squared();
+                let cluster = if dist0 < dist1 { 0 } else { 1 };
+                new_cluster_centers[cluster] += *lab;
+                new_cluster_colors[cluster] += colors[ri][ci];
+                new_cluster_counts[cluster] += 1;
+                is_foreground[ri][ci] = cluster == 0;
             }
         }
         // average the cluster centers
-        if new_cluster_counts[0] != 0 {
-            cluster_centers[0] = new_cluster_centers[0] / new_cluster_counts[0] as f64;
+        let mut changed = false;
+        for cluster in 0..2 {
+            if new_cluster_counts[cluster] == 0 {
+                continue;
+            }
+            let count = new_cluster_counts[cluster] as f64;
+            let center = new_cluster_centers[cluster] / count;
+            changed |= center != cluster_centers[cluster];
+            cluster_centers[cluster] = center;
+            cluster_colors[cluster] = new_cluster_colors[cluster] / count;
         }
-        if new_cluster_counts[1] != 0 {
-            cluster_centers[1] = new_cluster_centers[1] / new_cluster_counts[1] as f64;
+        if !changed {
+            break;
         }
     }
 
-    // return the average of the cluster centers
-    (cluster_centers[0], cluster_centers[1])
+    (cluster_colors[0], cluster_colors[1])
 }
 
 #[rustfmt::skip]
@@ -262,6 +298,62 @@ mod tests {
         assert_eq!(color_second, Color::white());
     }
 
+    // a gray gradient from top to bottom that looks evenly spaced, with OKLab lightness
+    // going from `low` to `high`
+    fn gradient(low: f64, high: f64) -> [[Color; SUBPIXEL_X]; SUBPIXEL_Y] {
+        std::array::from_fn(|y| {
+            std::array::from_fn(|x| {
+                let lightness = low + (high - low) * (y * SUBPIXEL_X + x) as f64 / 7.0;
+                Color::new(1.0, 1.0, 1.0) * lightness.powi(3)
+            })
+        })
+    }
+
+    #[test]
+    fn kmeans_splits_gradients_where_they_look_half_way() {
+        // in linear RGB, the dark end of a gradient looks alike and five of the eight
+        // subpixels would end up in the darker cluster
+        for (low, high) in [(0.2, 0.55), (0.3, 1.0)] {
+            let colors = gradient(low, high);
+            let mut first_cluster = [[false; SUBPIXEL_X]; SUBPIXEL_Y];
+            let (color_first, color_second) =
+                foreground_background_detection(&colors, &mut first_cluster);
+            assert_eq!(
+                first_cluster,
+                [[true, true], [true, true], [false, false], [false, false]]
+            );
+            // the colors of the clusters are the averages of their linear colors
+            let average = |rows: &[[Color; SUBPIXEL_X]]| {
+                rows.iter()
+                    .flatten()
+                    .fold(Color::black(), |sum, c| sum + *c)
+                    / 4.0
+            };
+            assert!((color_first - average(&colors[..2])).length() < 1e-12);
+            assert!((color_second - average(&colors[2..])).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn kmeans_seeds_with_the_farthest_colors() {
+        // a dark gradient with one bright subpixel, which is the only one that stands out
+        let mut colors = gradient(0.0, 0.1);
+        colors[2][1] = Color::white();
+        let mut first_cluster = [[false; SUBPIXEL_X]; SUBPIXEL_Y];
+        let (_, color_second) = foreground_background_detection(&colors, &mut first_cluster);
+        assert_eq!(
+            first_cluster,
+            [[true, true], [true, true], [true, false], [true, true]]
+        );
+        assert_eq!(color_second, Color::white());
+
+        // a pixel of a single color has nothing to split
+        let colors = [[Color::new(0.2, 0.3, 0.4); SUBPIXEL_X]; SUBPIXEL_Y];
+        let (color_first, color_second) =
+            foreground_background_detection(&colors, &mut first_cluster);
+        assert_eq!((color_first, color_second), (colors[0][0], colors[0][0]));
+    }
+
     #[test]
     fn top_left_subpixel_to_braille() {
         let mut render_pixel = RenderPixel::new();
@@ -0,0 +1,228 @@
+use crate::color::Color;
+use crate::image::HdrImage;
+use crate::maths::{Point, Vec2};
+use crate::noise::Perlin;
+use std::sync::Arc;
+
+// A color that varies over the surface of an object. Textures are evaluated with the
+// texture coordinates of the hit point, which depend on the shape, and the point itself
+// for textures that fill space
+pub trait Texture: Send + Sync {
+    fn value(&self, uv: Vec2, point: Point) -> Color;
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _uv: Vec2, _point: Point) -> Color {
+        self.color
+    }
+}
+
+// A checker pattern that fills space with cubes of alternating textures, so it looks the
+// same on any shape, independent of its texture coordinates
+pub struct CheckerTexture {
+    inverse_scale: f64,
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+}
+
+impl CheckerTexture {
+    // scale is the edge length of the cubes
+    pub fn new(scale: f64, even: Arc<dyn Texture>, odd: Arc<dyn Texture>) -> Self {
+        Self {
+            inverse_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+
+    pub fn from_colors(scale: f64, even: Color, odd: Color) -> Self {
+        Self::new(
+            scale,
+            Arc::new(SolidColor::new(even)),
+            Arc::new(SolidColor::new(odd)),
+        )
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, uv: Vec2, point: Point) -> Color {
+        let cell = |value: f64| (value * self.inverse_scale).floor() as i64;
+        let sum = cell(point.x) + cell(point.y) + cell(point.z);
+        if sum.rem_euclid(2) == 0 {
+            self.even.value(uv, point)
+        } else {
+            self.odd.value(uv, point)
+        }
+    }
+}
+
+// An image mapped onto the texture coordinates, u goes from left to right and v from the
+// bottom to the top. Coordinates outside of [0, 1] repeat the image
+pub struct ImageTexture {
+    image: HdrImage,
+}
+
+impl ImageTexture {
+    pub fn new(image: HdrImage) -> Self {
+        Self { image }
+    }
+}
+
+impl Texture for ImageTexture {
+    // bilinear filtering between the four pixels around the sampled position
+    fn value(&self, uv: Vec2, _point: Point) -> Color {
+        let (width, height) = (self.image.width, self.image.height);
+        if width == 0 || height == 0 {
+            return Color::pink();
+        }
+
+        // pixel centers are at half-integer positions
+        let x = uv.x.rem_euclid(1.0) * width as f64 - 0.5;
+        let y = (1.0 - uv.y.rem_euclid(1.0)) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+
+        let pixel = |x: f64, y: f64| {
+            let x = (x as i64).rem_euclid(width as i64) as usize;
+            let y = (y as i64).rem_euclid(height as i64) as usize;
+            self.image.get_pixel(x, y)
+        };
+        let top = Color::lerp(pixel(x0, y0), pixel(x0 + 1.0, y0), tx);
+        let bottom = Color::lerp(pixel(x0, y0 + 1.0), pixel(x0 + 1.0, y0 + 1.0), tx);
+        Color::lerp(top, bottom, ty)
+    }
+}
+
+// the procedural patterns of a NoiseTexture
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoisePattern {
+    // veins from a sine wave along z that is disturbed by turbulence
+    Marble,
+    // rings around the y axis with slightly irregular distances
+    Wood,
+    // soft fractal noise
+    Clouds,
+}
+
+// A pattern computed from Perlin noise at the hit point, which blends between two colors.
+// Like the checker texture, it fills space and does not depend on texture coordinates
+pub struct NoiseTexture {
+    perlin: Perlin,
+    pattern: NoisePattern,
+    scale: f64,
+    octaves: usize,
+    low: Color,
+    high: Color,
+}
+
+impl NoiseTexture {
+    // scale is the frequency of the pattern, low and high are the colors at the ends of
+    // its range
+    pub fn new(
+        pattern: NoisePattern,
+        seed: u64,
+        scale: f64,
+        octaves: usize,
+        low: Color,
+        high: Color,
+    ) -> Self {
+        Self {
+            perlin: Perlin::new(seed),
+            pattern,
+            scale,
+            octaves,
+            low,
+            high,
+        }
+    }
+
+    // the pattern value at the point, between 0 and 1
+    fn intensity(&self, point: Point) -> f64 {
+        let p = point * self.scale;
+        match self.pattern {
+            // the turbulence is not scaled, so the scale only sets the distance of the veins
+            NoisePattern::Marble => {
+                0.5 * (1.0 + (p.z + 10.0 * self.perlin.turbulence(point, self.octaves)).sin())
+            }
+            NoisePattern::Wood => {
+                let radius = (p.x * p.x + p.z * p.z).sqrt();
+                let rings = radius + 2.0 * self.perlin.turbulence(point, self.octaves);
+                // sharp edges where one ring ends and the next starts
+                (rings - rings.floor()).powi(3)
+            }
+            NoisePattern::Clouds => {
+                // the fractal sum of the signed noise stays roughly within [-1, 1]
+                let mut sum = 0.0;
+                let mut weight = 1.0;
+                let mut p = p;
+                for _ in 0..self.octaves.max(1) {
+                    sum += weight * self.perlin.noise(p);
+                    weight *= 0.5;
+                    p = p * 2.0;
+                }
+                (0.5 * (sum + 1.0)).clamp(0.0, 1.0)
+            }
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _uv: Vec2, point: Point) -> Color {
+        Color::lerp(self.low, self.high, self.intensity(point))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checker_alternates_in_all_directions() {
+        let checker = CheckerTexture::from_colors(0.5, Color::black(), Color::white());
+        let value = |x, y, z| checker.value(Vec2::zero(), Point::new(x, y, z));
+        assert_eq!(value(0.25, 0.25, 0.25), Color::black());
+        assert_eq!(value(0.75, 0.25, 0.25), Color::white());
+        assert_eq!(value(0.25, -0.25, 0.25), Color::white());
+        assert_eq!(value(0.75, 0.75, 0.75), Color::white());
+    }
+
+    #[test]
+    fn noise_patterns_stay_between_their_colors() {
+        for pattern in [
+            NoisePattern::Marble,
+            NoisePattern::Wood,
+            NoisePattern::Clouds,
+        ] {
+            let texture = NoiseTexture::new(pattern, 3, 4.0, 7, Color::black(), Color::white());
+            for i in 0..200 {
+                let t = i as f64 * 0.05;
+                let color = texture.value(Vec2::zero(), Point::new(t, -0.3 * t, 1.7 * t));
+                assert!((0.0..=1.0).contains(&color.x), "{:?}: {:?}", pattern, color);
+            }
+        }
+    }
+
+    #[test]
+    fn image_texture_filters_bilinearly() {
+        let mut image = HdrImage::new(2, 1);
+        image.set_pixel(1, 0, Color::white());
+        let texture = ImageTexture::new(image);
+        let value = |u| texture.value(Vec2::new(u, 0.5), Point::zero());
+        // pixel centers
+        assert_eq!(value(0.25), Color::black());
+        assert_eq!(value(0.75), Color::white());
+        // halfway between the pixels, and across the wrapped edge
+        assert_eq!(value(0.5), Color::new(0.5, 0.5, 0.5));
+        assert_eq!(value(0.0), Color::new(0.5, 0.5, 0.5));
+    }
+}
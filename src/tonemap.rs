@@ -0,0 +1,199 @@
+//! Maps the unbounded colors of a render to the [0, 1] range that a display can show.
+
+use crate::color::Color;
+
+// how colors above one are brought into range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapOperator {
+    // cuts every channel off at one, bright areas turn flat white
+    Clamp,
+    // Reinhard's L / (1 + L) on the luminance, which keeps the hue of bright colors
+    Reinhard,
+    // the filmic curve of the Academy Color Encoding System in Narkowicz's fit, with a
+    // toe in the shadows and a soft shoulder in the highlights
+    Aces,
+}
+
+impl ToneMapOperator {
+    pub const ALL: [ToneMapOperator; 3] = [
+        ToneMapOperator::Clamp,
+        ToneMapOperator::Reinhard,
+        ToneMapOperator::Aces,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::Aces => "aces",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|operator| operator.name() == name)
+    }
+
+    // the operator after this one, to cycle through all of them
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&operator| operator == self);
+        Self::ALL[index.map_or(0, |index| (index + 1) % Self::ALL.len())]
+    }
+
+    pub fn map(self, color: Color) -> Color {
+        match self {
+            ToneMapOperator::Clamp => color,
+            ToneMapOperator::Reinhard => color / (1.0 + color.luminance().max(0.0)),
+            ToneMapOperator::Aces => {
+                let curve = |x: f64| {
+                    let x = x.max(0.0);
+                    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+                };
+                Color::new(curve(color.x), curve(color.y), curve(color.z))
+            }
+        }
+        .saturated()
+    }
+}
+
+// The exposure and tone mapping for displaying a render. The exposure is in EV, every
+// step doubles the brightness. With auto exposure, it is relative to the exposure that
+// `auto_exposure` finds for the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    pub exposure: f64,
+    pub auto_exposure: bool,
+}
+
+impl Default for ToneMapping {
+    // shows the colors as they are
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+            auto_exposure: false,
+        }
+    }
+}
+
+impl ToneMapping {
+    // the total exposure in EV, given the automatic exposure of the image
+    pub fn total_exposure(&self, auto_exposure: f64) -> f64 {
+        if self.auto_exposure {
+            self.exposure + auto_exposure
+        } else {
+            self.exposure
+        }
+    }
+
+    pub fn apply(&self, color: Color, auto_exposure: f64) -> Color {
+        let scale = self.total_exposure(auto_exposure).exp2();
+        self.operator.map(color * scale)
+    }
+}
+
+// the brightness that a typical part of the image is exposed to
+const MIDDLE_GRAY: f64 = 0.18;
+// the histogram covers luminances from 2^MIN_EV to 2^MAX_EV in steps of a quarter EV
+const MIN_EV: f64 = -16.0;
+const MAX_EV: f64 = 16.0;
+const BINS: usize = 128;
+
+// The exposure in EV that brings the image to middle gray. It is based on a histogram of
+// the luminance. The darkest half and the brightest 5% of the pixels are ignored, so that
+// neither a black background nor a few bright lights take over. Black pixels do not count
+// at all, and an image without light keeps an exposure of zero.
+pub fn auto_exposure(colors: impl IntoIterator<Item = Color>) -> f64 {
+    let mut histogram = [0usize; BINS];
+    let mut count = 0;
+    for color in colors {
+        let luminance = color.luminance();
+        if luminance <= MIN_EV.exp2() {
+            continue;
+        }
+        let position = (luminance.log2() - MIN_EV) / (MAX_EV - MIN_EV) * BINS as f64;
+        histogram[(position as usize).min(BINS - 1)] += 1;
+        count += 1;
+    }
+    if count == 0 {
+        return 0.0;
+    }
+
+    let (low, high) = (count as f64 * 0.5, count as f64 * 0.95);
+    let mut below = 0.0;
+    let (mut sum, mut weight) = (0.0, 0.0);
+    for (bin, &bin_count) in histogram.iter().enumerate() {
+        // the part of this bin that lies between the two percentiles
+        let bin_count = bin_count as f64;
+        let inside = (below + bin_count).min(high) - below.max(low);
+        below += bin_count;
+        if inside > 0.0 {
+            let ev = MIN_EV + (bin as f64 + 0.5) / BINS as f64 * (MAX_EV - MIN_EV);
+            sum += ev * inside;
+            weight += inside;
+        }
+    }
+    if weight == 0.0 {
+        return 0.0;
+    }
+    MIDDLE_GRAY.log2() - sum / weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn operators_bring_colors_into_range() {
+        let bright = Color::new(8.0, 2.0, 0.5);
+        for operator in ToneMapOperator::ALL {
+            let mapped = operator.map(bright);
+            for channel in [mapped.x, mapped.y, mapped.z] {
+                assert!((0.0..=1.0).contains(&channel));
+            }
+            assert_eq!(ToneMapOperator::from_name(operator.name()), Some(operator));
+            // dark colors stay dark and ordered
+            let dark = operator.map(Color::new(0.1, 0.1, 0.1));
+            assert!(dark.x < 0.2 && dark.x < operator.map(Color::new(0.2, 0.2, 0.2)).x);
+        }
+        assert_eq!(
+            ToneMapOperator::Clamp.map(bright),
+            Color::new(1.0, 1.0, 0.5)
+        );
+        // unlike clamping, the others keep the highlights apart
+        let brighter = Color::new(16.0, 4.0, 1.0);
+        assert_ne!(
+            ToneMapOperator::Aces.map(bright),
+            ToneMapOperator::Aces.map(brighter)
+        );
+        let reinhard = ToneMapOperator::Reinhard.map(bright);
+        assert!(reinhard.x > reinhard.y && reinhard.y > reinhard.z);
+        assert_eq!(ToneMapOperator::Aces.next(), ToneMapOperator::Clamp);
+
+        let tone_mapping = ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: -1.0,
+            auto_exposure: false,
+        };
+        assert_eq!(
+            tone_mapping.apply(Color::new(1.0, 0.5, 0.0), 3.0),
+            Color::new(0.5, 0.25, 0.0)
+        );
+    }
+
+    #[test]
+    fn auto_exposure_brings_the_image_to_middle_gray() {
+        // a dim image with a black background and a few very bright pixels
+        let mut colors = vec![Color::black(); 500];
+        colors.extend(vec![Color::new(0.01, 0.01, 0.01); 1000]);
+        colors.extend(vec![Color::new(1000.0, 1000.0, 1000.0); 20]);
+        let exposure = auto_exposure(colors);
+        let exposed = 0.01 * exposure.exp2();
+        assert!((exposed - MIDDLE_GRAY).abs() < 0.02, "{}", exposed);
+
+        assert_eq!(auto_exposure(vec![Color::black(); 10]), 0.0);
+        assert_eq!(auto_exposure(Vec::new()), 0.0);
+    }
+}
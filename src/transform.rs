@@ -0,0 +1,265 @@
+use crate::aabb::Aabb;
+use crate::geometry::*;
+use crate::maths::*;
+use std::sync::Arc;
+
+// A transformation split into its parts: scale first, then rotate and then translate.
+// Unlike a matrix, two of them can be interpolated for animations.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransformParts {
+    pub scale: Vec3,
+    pub rotation: Quat,
+    pub translation: Vec3,
+}
+
+impl TransformParts {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    // the inverse matrix, built from the inverted parts in reverse order
+    fn inverse_matrix(&self) -> Mat4 {
+        let scale = Vec3::new(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z);
+        Mat4::scaling(scale)
+            * Mat4::rotation(self.rotation.conjugate())
+            * Mat4::translation(-self.translation)
+    }
+
+    // scale and translation are interpolated linearly, the rotation along the shorter arc
+    pub fn interpolate(a: &TransformParts, b: &TransformParts, t: f64) -> TransformParts {
+        TransformParts {
+            scale: Vec3::lerp(a.scale, b.scale, t),
+            rotation: Quat::slerp(a.rotation, b.rotation, t),
+            translation: Vec3::lerp(a.translation, b.translation, t),
+        }
+    }
+}
+
+enum Motion {
+    Static {
+        object_to_world: Mat4,
+        world_to_object: Mat4,
+    },
+    // moves from the start transformation at time 0 to the end at time 1
+    Animated {
+        start: TransformParts,
+        end: TransformParts,
+    },
+}
+
+// An object placed in the scene with a transformation. The object itself stays in its
+// own object space and is shared, so the same geometry can be placed many times without
+// copying it. Rays are mapped into object space for the intersection, and the hit point
+// and normal are mapped back into world space.
+pub struct Transformed<H: Hittable + ?Sized> {
+    object: Arc<H>,
+    motion: Motion,
+    bbox: Aabb,
+}
+
+impl<H: Hittable + ?Sized> Transformed<H> {
+    // returns None if the transformation cannot be inverted, e.g. a scale of zero
+    pub fn new(object: Arc<H>, object_to_world: Mat4) -> Option<Self> {
+        let world_to_object = object_to_world.inverse()?;
+        let bbox = transform_box(&object.bounding_box(), &object_to_world);
+        Some(Self {
+            object,
+            motion: Motion::Static {
+                object_to_world,
+                world_to_object,
+            },
+            bbox,
+        })
+    }
+
+    // an object that moves during the shutter interval, returns None if the scale is zero
+    // or changes its sign on the way, which would flatten the object at some time
+    pub fn animated(object: Arc<H>, start: TransformParts, end: TransformParts) -> Option<Self> {
+        let scales = [
+            (start.scale.x, end.scale.x),
+            (start.scale.y, end.scale.y),
+            (start.scale.z, end.scale.z),
+        ];
+        if scales.iter().any(|(a, b)| a * b <= 0.0) {
+            return None;
+        }
+        let bbox = animated_box(&object.bounding_box(), &start, &end);
+        Some(Self {
+            object,
+            motion: Motion::Animated { start, end },
+            bbox,
+        })
+    }
+
+    // the object to world and world to object matrices at the time
+    fn matrices(&self, time: f64) -> (Mat4, Mat4) {
+        match &self.motion {
+            Motion::Static {
+                object_to_world,
+                world_to_object,
+            } => (*object_to_world, *world_to_object),
+            Motion::Animated { start, end } => {
+                let parts = TransformParts::interpolate(start, end, time);
+                (parts.matrix(), parts.inverse_matrix())
+            }
+        }
+    }
+}
+
+// the box around the transformed corners of the box
+fn transform_box(bbox: &Aabb, matrix: &Mat4) -> Aabb {
+    if !bbox.is_bounded() {
+        return Aabb::universe();
+    }
+    let mut transformed = Aabb::empty();
+    for corner in corners(bbox) {
+        let corner = matrix.transform_point(corner);
+        transformed = Aabb::surrounding(&transformed, &Aabb::from_points(corner, corner));
+    }
+    transformed
+}
+
+fn corners(bbox: &Aabb) -> impl Iterator<Item = Point> + '_ {
+    (0..8).map(|i| {
+        Point::new(
+            if i & 1 == 0 { bbox.x.min } else { bbox.x.max },
+            if i & 2 == 0 { bbox.y.min } else { bbox.y.max },
+            if i & 4 == 0 { bbox.z.min } else { bbox.z.max },
+        )
+    })
+}
+
+// the box around the object at evenly spaced times, grown by how far the corners can
+// stray from the straight lines between two samples while rotating
+fn animated_box(bbox: &Aabb, start: &TransformParts, end: &TransformParts) -> Aabb {
+    if !bbox.is_bounded() {
+        return Aabb::universe();
+    }
+    let steps = 32;
+    let mut animated = Aabb::empty();
+    for step in 0..=steps {
+        let parts = TransformParts::interpolate(start, end, step as f64 / steps as f64);
+        animated = Aabb::surrounding(&animated, &transform_box(bbox, &parts.matrix()));
+    }
+
+    let max_scale = [start.scale, end.scale]
+        .iter()
+        .flat_map(|scale| [scale.x.abs(), scale.y.abs(), scale.z.abs()])
+        .fold(0.0, f64::max);
+    let radius = corners(bbox)
+        .map(|corner| corner.length())
+        .fold(0.0, f64::max)
+        * max_scale;
+    let cos = start.rotation.dot(&end.rotation).abs().min(1.0);
+    let angle_per_step = 2.0 * cos.acos() / steps as f64;
+    let margin = radius * (1.0 - (angle_per_step / 2.0).cos());
+    Aabb::new(
+        Interval::new(animated.x.min - margin, animated.x.max + margin),
+        Interval::new(animated.y.min - margin, animated.y.max + margin),
+        Interval::new(animated.z.min - margin, animated.z.max + margin),
+    )
+}
+
+impl<H: Hittable + ?Sized> Hittable for Transformed<H> {
+    fn hit(&self, ray: &Ray, interval: &Interval) -> Option<HitRecord> {
+        let (object_to_world, world_to_object) = self.matrices(ray.time);
+        // the direction is not normalized, so t is the same in both spaces
+        let object_ray = Ray::with_time(
+            world_to_object.transform_point(ray.origin),
+            world_to_object.transform_vector(ray.direction),
+            ray.time,
+        );
+        let mut record = self.object.hit(&object_ray, interval)?;
+
+        record.point = object_to_world.transform_point(record.point);
+        // normals are transformed with the inverse transpose to stay perpendicular to the
+        // surface, which also keeps them on the same side as the ray
+        record.normal = world_to_object
+            .transpose()
+            .transform_vector(record.normal)
+            .normalized();
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::materials::Lambertian;
+
+    #[test]
+    fn rotated_box_is_hit_in_world_space() {
+        let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let cuboid: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+            Point::new(-1.0, -1.0, -1.0),
+            Point::new(1.0, 1.0, 1.0),
+            material,
+        ));
+        // turned by 45 degrees around y, so a corner points at the ray, then moved back
+        let matrix = Mat4::from_scale_rotation_translation(
+            Vec3::one(),
+            Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), std::f64::consts::FRAC_PI_4),
+            Vec3::new(0.0, 0.0, -5.0),
+        );
+        let transformed = Transformed::new(Arc::clone(&cuboid), matrix).unwrap();
+
+        let ray = Ray::new(Point::new(0.3, 0.5, 0.0), Vec3::new(0.0, 0.0, -1.0));
+        let record = transformed
+            .hit(&ray, &Interval::new(0.001, f64::INFINITY))
+            .unwrap();
+        // the former +z side now faces diagonally towards the ray
+        let sqrt2 = 2.0_f64.sqrt();
+        assert!((record.t - (5.0 - sqrt2 + 0.3)).abs() < 1e-9);
+        assert!((record.point - Point::new(0.3, 0.5, -5.0 + sqrt2 - 0.3)).length() < 1e-9);
+        assert!(record.front_face);
+        assert!((record.normal - Vec3::new(1.0, 0.0, 1.0).normalized()).length() < 1e-9);
+
+        let bbox = transformed.bounding_box();
+        assert!((bbox.x.max - sqrt2).abs() < 1e-3);
+        assert!((bbox.z.min - (-5.0 - sqrt2)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn animated_transform_moves_with_the_ray_time() {
+        let material = Arc::new(Lambertian::new(Vec3::new(0.5, 0.5, 0.5)));
+        let cuboid: Arc<dyn Hittable> = Arc::new(Cuboid::new(
+            Point::new(-0.5, -0.5, -0.5),
+            Point::new(0.5, 0.5, 0.5),
+            material,
+        ));
+        let start = TransformParts {
+            scale: Vec3::one(),
+            rotation: Quat::identity(),
+            translation: Vec3::new(0.0, 0.0, -5.0),
+        };
+        let end = TransformParts {
+            scale: Vec3::new(2.0, 2.0, 2.0),
+            rotation: Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), std::f64::consts::PI),
+            translation: Vec3::new(4.0, 0.0, -5.0),
+        };
+        let transformed = Transformed::animated(cuboid, start, end).unwrap();
+        let interval = Interval::new(0.001, f64::INFINITY);
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let at = |x: f64, time: f64| Ray::with_time(Point::new(x, 0.0, 0.0), forward, time);
+
+        let record = transformed.hit(&at(0.0, 0.0), &interval).unwrap();
+        assert!((record.t - 4.5).abs() < 1e-9);
+        assert!(transformed.hit(&at(0.0, 1.0), &interval).is_none());
+        // twice the size at the end, so the front is one unit in front of the center
+        let record = transformed.hit(&at(4.0, 1.0), &interval).unwrap();
+        assert!((record.t - 4.0).abs() < 1e-9);
+        assert!((record.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+
+        // the box holds the object at every time, including the rotated corners halfway
+        let bbox = transformed.bounding_box();
+        for step in 0..=10 {
+            let parts = TransformParts::interpolate(&start, &end, step as f64 / 10.0);
+            let corner = parts.matrix().transform_point(Point::new(0.5, 0.5, 0.5));
+            assert!(bbox.x.contains(corner.x) && bbox.z.contains(corner.z));
+        }
+    }
+}